@@ -0,0 +1,451 @@
+use crate::attributes::{self, Attributes, State};
+use crate::cache;
+use crate::missing::Missing;
+use crate::repository::Repository;
+use chrono::prelude::*;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use std::io::{self, Seek, Write};
+use std::{fs, path};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    TarGz,
+    Zip,
+}
+
+pub static FORMATS: [Format; 2] = [Format::TarGz, Format::Zip];
+
+impl Format {
+    pub fn extension(&self) -> &str {
+        match self {
+            Format::TarGz => ".tar.gz",
+            Format::Zip => ".zip",
+        }
+    }
+
+    pub fn content_type(&self) -> &str {
+        match self {
+            Format::TarGz => "application/gzip",
+            Format::Zip => "application/zip",
+        }
+    }
+
+    // `main.tar.gz` is main as a tar.gz
+    pub fn split(name: &str) -> Option<(&str, Format)> {
+        for format in FORMATS.iter() {
+            if name.ends_with(format.extension()) {
+                return Some((&name[..name.len() - format.extension().len()], *format));
+            }
+        }
+        None
+    }
+}
+
+enum EntryKind {
+    Directory,
+    // with the executable bit or not
+    File(bool, Vec<u8>),
+    Symlink(String),
+}
+
+struct Entry {
+    path: String,
+    kind: EntryKind,
+}
+
+fn git_error(_: git2::Error) -> Missing {
+    Missing::Nowhere
+}
+
+fn info_attributes_path(repo: &Repository) -> path::PathBuf {
+    path::Path::new(&repo.path).join("info").join("attributes")
+}
+
+// the one in the bare repo, which beats all the .gitattributes files
+fn info_attributes(repo: &Repository) -> Option<Attributes> {
+    match fs::read_to_string(info_attributes_path(repo)) {
+        Ok(text) => Some(attributes::parse("", &text)),
+        Err(_) => None,
+    }
+}
+
+// where the entries go as the walk finds them, so only one file's in
+// memory at a time
+trait Output {
+    fn add(&mut self, entry: Entry) -> io::Result<()>;
+}
+
+struct TarGz<W: Write> {
+    builder: tar::Builder<GzEncoder<W>>,
+    prefix: String,
+    mtime: u64,
+}
+
+impl<W: Write> TarGz<W> {
+    fn new(output: W, prefix: String, mtime: u64) -> TarGz<W> {
+        TarGz {
+            builder: tar::Builder::new(GzEncoder::new(output, Compression::default())),
+            prefix,
+            mtime,
+        }
+    }
+
+    fn finish(self) -> io::Result<W> {
+        self.builder.into_inner()?.finish()
+    }
+}
+
+impl<W: Write> Output for TarGz<W> {
+    fn add(&mut self, entry: Entry) -> io::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(self.mtime);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_username("root")?;
+        header.set_groupname("root")?;
+        let path = format!("{}{}", self.prefix, entry.path);
+        match entry.kind {
+            EntryKind::Directory => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(0o755);
+                header.set_size(0);
+                self.builder.append_data(&mut header, path, io::empty())
+            }
+            EntryKind::File(executable, content) => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(if executable { 0o755 } else { 0o644 });
+                header.set_size(content.len() as u64);
+                self.builder.append_data(&mut header, path, &content[..])
+            }
+            EntryKind::Symlink(target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_mode(0o777);
+                header.set_size(0);
+                header.set_link_name(target)?;
+                self.builder.append_data(&mut header, path, io::empty())
+            }
+        }
+    }
+}
+
+struct Zip<W: Write + Seek> {
+    writer: zip::ZipWriter<W>,
+    prefix: String,
+    options: zip::write::FileOptions,
+}
+
+impl<W: Write + Seek> Zip<W> {
+    fn new(output: W, prefix: String, mtime: u64) -> Zip<W> {
+        // zip times can't be before 1980, and don't have a timezone, so this
+        // is the time in utc
+        let time = Utc.timestamp(mtime as i64, 0);
+        let time = zip::DateTime::from_date_and_time(
+            time.year() as u16,
+            time.month() as u8,
+            time.day() as u8,
+            time.hour() as u8,
+            time.minute() as u8,
+            time.second() as u8,
+        )
+        .unwrap_or_default();
+        Zip {
+            writer: zip::ZipWriter::new(output),
+            prefix,
+            options: zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated)
+                .last_modified_time(time),
+        }
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        Ok(self.writer.finish()?)
+    }
+}
+
+impl<W: Write + Seek> Output for Zip<W> {
+    fn add(&mut self, entry: Entry) -> io::Result<()> {
+        let path = format!("{}{}", self.prefix, entry.path);
+        let options = self.options;
+        match entry.kind {
+            EntryKind::Directory => {
+                self.writer
+                    .add_directory(path, options.unix_permissions(0o755))?;
+            }
+            EntryKind::File(executable, content) => {
+                let mode = if executable { 0o755 } else { 0o644 };
+                self.writer
+                    .start_file(path, options.unix_permissions(mode))?;
+                self.writer.write_all(&content)?;
+            }
+            // zip has no symlinks of its own, so it gets what git would
+            // check out on a computer without them
+            EntryKind::Symlink(target) => {
+                self.writer
+                    .start_file(path, options.unix_permissions(0o644))?;
+                self.writer.write_all(target.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Walk<'a> {
+    repo: &'a Repository,
+    info: Option<Attributes>,
+    // the .gitattributes from the root down to where we are
+    stack: Vec<Attributes>,
+    // the directory the archive is of, like `docs/`, which gets left off the
+    // paths in it
+    root: String,
+    output: &'a mut dyn Output,
+}
+
+impl Walk<'_> {
+    fn add(&mut self, entry: Entry) -> Result<(), Missing> {
+        self.output.add(entry).map_err(|_| Missing::Nowhere)
+    }
+
+    fn export_ignored(&self, path: &str, is_directory: bool) -> bool {
+        let layers = self.info.iter().chain(self.stack.iter().rev());
+        matches!(
+            attributes::get(layers, path, is_directory, "export-ignore"),
+            Some(State::Set) | Some(State::Value(_))
+        )
+    }
+
+    // puts the tree's .gitattributes on the stack, if it has one
+    fn push_attributes(&mut self, tree: &git2::Tree, directory: &str) -> bool {
+        let gitattributes = match tree.get_name(".gitattributes") {
+            Some(entry) => match entry.to_object(&self.repo.git2) {
+                Ok(object) => object
+                    .as_blob()
+                    .map(|blob| String::from_utf8_lossy(blob.content()).into_owned()),
+                Err(_) => None,
+            },
+            None => None,
+        };
+        match gitattributes {
+            Some(text) => {
+                self.stack.push(attributes::parse(directory, &text));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // directory is `` at the top, or `src/` further in
+    fn walk(&mut self, tree: &git2::Tree, directory: &str) -> Result<(), Missing> {
+        let pushed = self.push_attributes(tree, directory);
+
+        for entry in tree.iter() {
+            let name = match entry.name() {
+                Some(name) => name,
+                None => continue,
+            };
+            let path = format!("{}{}", directory, name);
+            let is_directory = entry.kind() != Some(git2::ObjectType::Blob);
+            if self.export_ignored(&path, is_directory) {
+                continue;
+            }
+            let relative = path[self.root.len()..].to_owned();
+            match entry.kind() {
+                Some(git2::ObjectType::Tree) => {
+                    self.add(Entry {
+                        path: format!("{}/", relative),
+                        kind: EntryKind::Directory,
+                    })?;
+                    let subtree = self.repo.git2.find_tree(entry.id()).map_err(git_error)?;
+                    self.walk(&subtree, &format!("{}/", path))?;
+                }
+                Some(git2::ObjectType::Blob) => {
+                    let blob = self.repo.git2.find_blob(entry.id()).map_err(git_error)?;
+                    let content = blob.content().to_vec();
+                    let kind = match entry.filemode() {
+                        0o120000 => {
+                            EntryKind::Symlink(String::from_utf8_lossy(&content).into_owned())
+                        }
+                        0o100755 => EntryKind::File(true, content),
+                        _ => EntryKind::File(false, content),
+                    };
+                    self.add(Entry {
+                        path: relative,
+                        kind,
+                    })?;
+                }
+                // submodules, which git archive leaves as empty directories
+                _ => self.add(Entry {
+                    path: format!("{}/", relative),
+                    kind: EntryKind::Directory,
+                })?,
+            }
+        }
+
+        if pushed {
+            self.stack.pop();
+        }
+        Ok(())
+    }
+}
+
+// `repo-ref` or `repo-ref-directory`, which is the folder everything's in
+// and the name of the file
+fn name(repo: &Repository, refname: &str, directory: &str) -> String {
+    let name = if directory.is_empty() {
+        format!("{}-{}", repo.name, refname)
+    } else {
+        format!("{}-{}-{}", repo.name, refname, directory)
+    };
+    name.replace('/', "-")
+}
+
+// everything in the directory of the tree, through output
+fn fill<'a>(
+    repo: &'a Repository,
+    directory: &str,
+    mut tree: git2::Tree<'a>,
+    output: &mut dyn Output,
+) -> Result<(), Missing> {
+    let mut walk = Walk {
+        repo,
+        info: info_attributes(repo),
+        stack: vec![],
+        root: String::new(),
+        output,
+    };
+
+    // the .gitattributes above the directory still count, and it's not
+    // there at all if one of them export-ignores it
+    for name in directory.split('/').filter(|name| !name.is_empty()) {
+        let root = walk.root.clone();
+        walk.push_attributes(&tree, &root);
+        walk.root = format!("{}{}/", root, name);
+        if walk.export_ignored(&walk.root[..walk.root.len() - 1], true) {
+            return Err(Missing::Nowhere);
+        }
+        let id = match tree.get_name(name) {
+            Some(entry) => entry.id(),
+            None => return Err(Missing::Nowhere),
+        };
+        tree = repo.git2.find_tree(id).map_err(git_error)?;
+    }
+    // the prefix itself
+    walk.add(Entry {
+        path: String::new(),
+        kind: EntryKind::Directory,
+    })?;
+    let root = walk.root.clone();
+    walk.walk(&tree, &root)
+}
+
+fn make<'a>(
+    repo: &'a Repository,
+    refname: &str,
+    directory: &str,
+    tree: git2::Tree<'a>,
+    mtime: u64,
+    format: Format,
+    file: &mut fs::File,
+) -> Result<(), Missing> {
+    let prefix = format!("{}/", name(repo, refname, directory));
+    let file = io::BufWriter::new(file);
+    let written = match format {
+        Format::TarGz => {
+            let mut output = TarGz::new(file, prefix, mtime);
+            fill(repo, directory, tree, &mut output)?;
+            output.finish()
+        }
+        Format::Zip => {
+            let mut output = Zip::new(file, prefix, mtime);
+            fill(repo, directory, tree, &mut output)?;
+            output.finish()
+        }
+    };
+    match written.and_then(|mut file| file.flush()) {
+        Ok(()) => Ok(()),
+        Err(_) => Err(Missing::Nowhere),
+    }
+}
+
+// what goes in the archive depends on the tree it's of, and on the attributes
+// that can leave things out of it from above: info/attributes, and the
+// .gitattributes in the directories on the way down. this is the tree's id,
+// and a hash of the rest
+fn source(
+    repo: &Repository,
+    tree: &git2::Tree,
+    directory: &str,
+) -> Result<(git2::Oid, String), Missing> {
+    let mut hasher = Sha256::new();
+    let info = fs::read(info_attributes_path(repo)).unwrap_or_default();
+    hasher.input((info.len() as u64).to_be_bytes());
+    hasher.input(&info);
+    let mut id = tree.id();
+    for name in directory.split('/').filter(|name| !name.is_empty()) {
+        let tree = repo.git2.find_tree(id).map_err(git_error)?;
+        if let Some(entry) = tree.get_name(".gitattributes") {
+            hasher.input(entry.id().as_bytes());
+        }
+        id = match tree.get_name(name) {
+            Some(entry) => entry.id(),
+            None => return Err(Missing::Nowhere),
+        };
+    }
+    // a short one is plenty to tell versions of the attributes apart
+    let hash: String = hasher.result()[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok((id, hash))
+}
+
+// everything at the ref, or in one directory of it, in `repo-ref/` or
+// `repo-ref-directory/`. dated when the commit was made, and cached, so the
+// same tag always gives the same bytes. it's made straight into the cache,
+// and what comes back is where, with an etag for resuming it
+pub fn archive(
+    repo: &Repository,
+    refname: &str,
+    directory: Option<&str>,
+    format: Format,
+) -> Result<(String, path::PathBuf), Missing> {
+    let directory = directory.unwrap_or("").trim_matches('/');
+    let mtime = match repo.get_ref(refname)?.peel_to_commit() {
+        Ok(commit) => commit.time().seconds() as u64,
+        Err(_) => 0,
+    };
+    let tree = repo.git2_tree(refname, None)?;
+    let (subtree, attributes) = source(repo, &tree, directory)?;
+    // the name and the time are in the archive too, so two tags on the same
+    // tree still get their own
+    let key = format!(
+        "{}-{}-{}-{}{}",
+        subtree,
+        attributes,
+        mtime,
+        name(repo, refname, directory),
+        format.extension()
+    );
+    let archive = cache::get_or_write("archive", repo, &key, |file| {
+        make(repo, refname, directory, tree, mtime, format, file)
+    })?;
+    // the rest of the key comes from the url
+    let etag = format!("\"{}-{}-{}\"", subtree, attributes, mtime);
+    Ok((etag, archive))
+}
+
+// a line `sha256sum -c` can check
+pub fn checksum(archive: &path::Path, filename: &str) -> Result<String, Missing> {
+    let mut hasher = Sha256::new();
+    let hashed = fs::File::open(archive).and_then(|mut file| io::copy(&mut file, &mut hasher));
+    if hashed.is_err() {
+        return Err(Missing::Nowhere);
+    }
+    let hash: String = hasher
+        .result()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok(format!("{}  {}\n", hash, filename))
+}
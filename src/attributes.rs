@@ -0,0 +1,202 @@
+// just enough of .gitattributes to answer things like "is this
+// export-ignore", straight out of the trees, since a bare repo has no
+// checkout for libgit2 to read them from
+// https://git-scm.com/docs/gitattributes
+
+#[derive(Debug, PartialEq)]
+pub enum State {
+    // `name`
+    Set,
+    // `-name`
+    Unset,
+    // `!name`, which takes back whatever a less specific file said
+    Unspecified,
+    // `name=value`
+    Value(String),
+}
+
+struct Rule {
+    pattern: String,
+    // `dir/` only matches directories
+    directory_only: bool,
+    attributes: Vec<(String, State)>,
+}
+
+// the rules from one .gitattributes file, and where it lives
+pub struct Attributes {
+    // `` for the root, `docs/` for docs/.gitattributes
+    base: String,
+    rules: Vec<Rule>,
+}
+
+fn parse_attribute(attribute: &str) -> (String, State) {
+    if attribute.starts_with('-') {
+        (attribute[1..].to_owned(), State::Unset)
+    } else if attribute.starts_with('!') {
+        (attribute[1..].to_owned(), State::Unspecified)
+    } else {
+        let mut parts = attribute.splitn(2, '=');
+        let name = parts.next().unwrap_or("").to_owned();
+        match parts.next() {
+            Some(value) => (name, State::Value(value.to_owned())),
+            None => (name, State::Set),
+        }
+    }
+}
+
+pub fn parse(base: &str, text: &str) -> Attributes {
+    let mut rules = vec![];
+    for line in text.lines() {
+        let line = line.trim();
+        // macros are for the attributes we don't know about
+        if line.is_empty() || line.starts_with('#') || line.starts_with("[attr]") {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let pattern = match words.next() {
+            Some(pattern) => pattern,
+            None => continue,
+        };
+        let (pattern, directory_only) = if pattern.ends_with('/') {
+            (&pattern[..pattern.len() - 1], true)
+        } else {
+            (pattern, false)
+        };
+        rules.push(Rule {
+            pattern: pattern.to_owned(),
+            directory_only,
+            attributes: words.map(parse_attribute).collect(),
+        });
+    }
+    Attributes {
+        base: base.to_owned(),
+        rules,
+    }
+}
+
+// `[a-z]`, `[!0-9]` and friends. returns whether it matched and how much of
+// the pattern the class took up
+fn match_class(pattern: &[u8], character: u8) -> Option<(bool, usize)> {
+    let mut index = 1;
+    let negated = match pattern.get(index) {
+        Some(b'!') | Some(b'^') => {
+            index += 1;
+            true
+        }
+        _ => false,
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let start = *pattern.get(index)?;
+        if start == b']' && !first {
+            break;
+        }
+        first = false;
+        if pattern.get(index + 1) == Some(&b'-')
+            && pattern.get(index + 2).map_or(false, |c| *c != b']')
+        {
+            let end = pattern[index + 2];
+            matched |= start <= character && character <= end;
+            index += 3;
+        } else {
+            matched |= start == character;
+            index += 1;
+        }
+    }
+    Some((matched != negated, index + 1))
+}
+
+// wildmatch, where `*` and `?` stop at slashes and `**` doesn't
+pub fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.get(1) == Some(&b'*') => {
+            let rest = &pattern[2..];
+            // `a/**/b` matches `a/b` too
+            if rest.first() == Some(&b'/') && glob(&rest[1..], text) {
+                return true;
+            }
+            (0..=text.len()).any(|index| glob(rest, &text[index..]))
+        }
+        Some(b'*') => {
+            let rest = &pattern[1..];
+            for index in 0..=text.len() {
+                if glob(rest, &text[index..]) {
+                    return true;
+                }
+                if index < text.len() && text[index] == b'/' {
+                    break;
+                }
+            }
+            false
+        }
+        Some(b'?') => match text.first() {
+            Some(character) if *character != b'/' => glob(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some(b'[') => match (
+            text.first(),
+            match_class(pattern, *text.first().unwrap_or(&0)),
+        ) {
+            (Some(character), Some((true, length))) if *character != b'/' => {
+                glob(&pattern[length..], &text[1..])
+            }
+            // an unclosed [ is just a [
+            (Some(b'['), None) => glob(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some(b'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob(&pattern[2..], &text[1..])
+        }
+        Some(character) => text.first() == Some(character) && glob(&pattern[1..], &text[1..]),
+    }
+}
+
+impl Rule {
+    // the path is relative to the .gitattributes file
+    fn matches(&self, path: &str, is_directory: bool) -> bool {
+        if self.directory_only && !is_directory {
+            return false;
+        }
+        // without a slash it's any file with that name, at any depth
+        if !self.pattern.contains('/') {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            return glob(self.pattern.as_bytes(), name.as_bytes());
+        }
+        let pattern = self.pattern.trim_start_matches('/');
+        glob(pattern.as_bytes(), path.as_bytes())
+    }
+}
+
+impl Attributes {
+    fn get(&self, path: &str, is_directory: bool, name: &str) -> Option<&State> {
+        if !path.starts_with(&self.base) {
+            return None;
+        }
+        let path = &path[self.base.len()..];
+        // later lines win
+        for rule in self.rules.iter().rev() {
+            for (attribute, state) in rule.attributes.iter().rev() {
+                if attribute == name && rule.matches(path, is_directory) {
+                    return Some(state);
+                }
+            }
+        }
+        None
+    }
+}
+
+// the layers go from the most important, like info/attributes or the
+// deepest .gitattributes, to the least
+pub fn get<'a, I>(layers: I, path: &str, is_directory: bool, name: &str) -> Option<&'a State>
+where
+    I: IntoIterator<Item = &'a Attributes>,
+{
+    for layer in layers {
+        if let Some(state) = layer.get(path, is_directory, name) {
+            return Some(state);
+        }
+    }
+    None
+}
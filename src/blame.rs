@@ -0,0 +1,210 @@
+use crate::missing::Missing;
+use crate::repository::Repository;
+use std::collections::{HashMap, HashSet};
+use std::path;
+
+// commits listed in here, like the one that ran rustfmt over everything,
+// get skipped and the lines they touched go to whoever wrote them before
+// https://git-scm.com/docs/git-blame#Documentation/git-blame.txt---ignore-revs-fileltfilegt
+static IGNORE_REVS_PATH: &str = ".git-blame-ignore-revs";
+
+// some lines in a row that the same commit last touched
+pub struct Hunk<'repo> {
+    pub commit: git2::Commit<'repo>,
+    // the first line's number, counting from 1
+    pub start: usize,
+    pub lines: usize,
+}
+
+// where a line came from: the commit, the file's path in it, and the line's
+// number in that version of the file
+#[derive(Clone)]
+struct Origin {
+    commit: git2::Oid,
+    path: String,
+    line: usize,
+}
+
+fn git_error(_: git2::Error) -> Missing {
+    Missing::Nowhere
+}
+
+fn ignore_revs(repo: &Repository, tree: &git2::Tree) -> HashSet<git2::Oid> {
+    let mut ignored = HashSet::new();
+    let blob = match tree.get_name(IGNORE_REVS_PATH) {
+        Some(entry) => match repo.git2.find_blob(entry.id()) {
+            Ok(blob) => blob,
+            Err(_) => return ignored,
+        },
+        None => return ignored,
+    };
+    for line in String::from_utf8_lossy(blob.content()).lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Ok(object) = repo.git2.revparse_single(line) {
+            if let Ok(commit) = object.peel_to_commit() {
+                ignored.insert(commit.id());
+            }
+        }
+    }
+    ignored
+}
+
+// where every line of the file at that commit came from
+fn origins(repo: &Repository, commit: git2::Oid, path: &str) -> Result<Vec<Origin>, Missing> {
+    let mut options = git2::BlameOptions::new();
+    options.newest_commit(commit);
+    let blame = repo
+        .git2
+        .blame_file(path::Path::new(path), Some(&mut options))
+        .map_err(git_error)?;
+    let mut origins = vec![];
+    for hunk in blame.iter() {
+        let orig_path = match hunk.path().and_then(|path| path.to_str()) {
+            Some(orig_path) => orig_path.to_owned(),
+            None => path.to_owned(),
+        };
+        for index in 0..hunk.lines_in_hunk() {
+            origins.push(Origin {
+                commit: hunk.final_commit_id(),
+                path: orig_path.clone(),
+                line: hunk.orig_start_line() + index,
+            });
+        }
+    }
+    Ok(origins)
+}
+
+// which line in the old blob each line in the new one was, or None for the
+// lines that were added out of nowhere. a changed line is taken to be the
+// line it replaced, the same guess git makes when it can't do better
+fn line_map(old: &git2::Blob, new: &git2::Blob) -> Result<Vec<Option<usize>>, Missing> {
+    let mut options = git2::DiffOptions::new();
+    options.context_lines(0);
+    let patch =
+        git2::Patch::from_blobs(old, None, new, None, Some(&mut options)).map_err(git_error)?;
+    let new_lines = new.content().split(|byte| *byte == b'\n').count();
+    let mut map = vec![];
+    // how far ahead the old file is of the new one
+    let mut offset: isize = 0;
+    for hunk_index in 0..patch.num_hunks() {
+        let (hunk, line_count) = patch.hunk(hunk_index).map_err(git_error)?;
+        let mut deleted = vec![];
+        let mut added = vec![];
+        for line_index in 0..line_count {
+            let line = patch
+                .line_in_hunk(hunk_index, line_index)
+                .map_err(git_error)?;
+            match line.origin() {
+                '-' => deleted.extend(line.old_lineno()),
+                '+' => added.extend(line.new_lineno()),
+                _ => {}
+            }
+        }
+        // the unchanged lines before the hunk
+        let hunk_start = if hunk.new_lines() == 0 {
+            hunk.new_start() + 1
+        } else {
+            hunk.new_start()
+        } as usize;
+        while map.len() + 1 < hunk_start {
+            let line = map.len() + 1;
+            map.push(Some((line as isize + offset) as usize));
+        }
+        for (index, _) in added.iter().enumerate() {
+            let replaced = deleted.get(index).or_else(|| deleted.last());
+            map.push(replaced.map(|line| *line as usize));
+        }
+        offset += hunk.old_lines() as isize - hunk.new_lines() as isize;
+    }
+    while map.len() < new_lines {
+        let line = map.len() + 1;
+        map.push(Some((line as isize + offset) as usize));
+    }
+    Ok(map)
+}
+
+// what's been worked out for a file at a commit, since every line an ignored
+// commit touched needs the same things
+#[derive(Default)]
+struct Known {
+    origins: HashMap<(git2::Oid, String), Vec<Origin>>,
+    line_maps: HashMap<(git2::Oid, String), Vec<Option<usize>>>,
+}
+
+// git2's blame, but with ignored commits' lines handed back to their parents
+fn resolve(
+    repo: &Repository,
+    origin: Origin,
+    ignored: &HashSet<git2::Oid>,
+    known: &mut Known,
+) -> Result<git2::Oid, Missing> {
+    let mut origin = origin;
+    // an ignored commit could be ignored again further back, but never the
+    // same one twice
+    let mut seen = HashSet::new();
+    while ignored.contains(&origin.commit) && seen.insert(origin.commit) {
+        let commit = repo.git2.find_commit(origin.commit).map_err(git_error)?;
+        let parent = match commit.parents().next() {
+            Some(parent) => parent,
+            None => break,
+        };
+        let key = (origin.commit, origin.path.clone());
+        if !known.line_maps.contains_key(&key) {
+            let file_path = path::Path::new(&origin.path);
+            let new = match commit.tree().map_err(git_error)?.get_path(file_path) {
+                Ok(entry) => repo.git2.find_blob(entry.id()).map_err(git_error)?,
+                Err(_) => break,
+            };
+            let old = match parent.tree().map_err(git_error)?.get_path(file_path) {
+                Ok(entry) => repo.git2.find_blob(entry.id()).map_err(git_error)?,
+                Err(_) => break,
+            };
+            known.line_maps.insert(key.clone(), line_map(&old, &new)?);
+        }
+        let line = match known.line_maps[&key].get(origin.line - 1) {
+            Some(Some(line)) => *line,
+            _ => break,
+        };
+
+        let key = (parent.id(), origin.path.clone());
+        if !known.origins.contains_key(&key) {
+            let origins = origins(repo, parent.id(), &origin.path)?;
+            known.origins.insert(key.clone(), origins);
+        }
+        origin = match known.origins[&key].get(line - 1) {
+            Some(origin) => origin.clone(),
+            None => break,
+        };
+    }
+    Ok(origin.commit)
+}
+
+pub fn blame<'repo>(
+    repo: &'repo Repository,
+    refname: &str,
+    path: &str,
+) -> Result<Vec<Hunk<'repo>>, Missing> {
+    let commit = repo.get_ref(refname)?.peel_to_commit().map_err(git_error)?;
+    let ignored = ignore_revs(repo, &commit.tree().map_err(git_error)?);
+    let mut known = Known::default();
+
+    let mut hunks: Vec<Hunk> = vec![];
+    for (index, origin) in origins(repo, commit.id(), path)?.into_iter().enumerate() {
+        let id = resolve(repo, origin, &ignored, &mut known)?;
+        if let Some(hunk) = hunks.last_mut() {
+            if hunk.commit.id() == id {
+                hunk.lines += 1;
+                continue;
+            }
+        }
+        hunks.push(Hunk {
+            commit: repo.git2.find_commit(id).map_err(git_error)?,
+            start: index + 1,
+            lines: 1,
+        });
+    }
+    Ok(hunks)
+}
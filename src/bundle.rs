@@ -0,0 +1,84 @@
+use crate::cache;
+use crate::missing::Missing;
+use crate::repository::Repository;
+use crate::upload_pack;
+
+// https://git-scm.com/docs/gitformat-bundle
+pub const CONTENT_TYPE: &str = "application/x-git-bundle";
+
+const SIGNATURE: &str = "# v2 git bundle\n";
+
+struct Tip {
+    id: git2::Oid,
+    // what HEAD is in the bundle, because it can't be a tag
+    commit: Option<git2::Oid>,
+    refname: String,
+}
+
+fn resolve(repo: &Repository, refname: &str) -> Result<Tip, Missing> {
+    let reference = repo.get_ref(refname)?;
+    let reference = match reference.resolve() {
+        Ok(reference) => reference,
+        Err(_) => return Err(Missing::Nowhere),
+    };
+    let commit = reference.peel_to_commit().ok().map(|commit| commit.id());
+    match (reference.target(), reference.name()) {
+        (Some(id), Some(name)) => Ok(Tip {
+            id,
+            commit,
+            refname: name.to_owned(),
+        }),
+        _ => Err(Missing::Nowhere),
+    }
+}
+
+fn resolve_commit(repo: &Repository, refname: &str) -> Result<git2::Oid, Missing> {
+    match repo.get_ref(refname)?.peel_to_commit() {
+        Ok(commit) => Ok(commit.id()),
+        Err(_) => Err(Missing::Nowhere),
+    }
+}
+
+// `main` for everything, or `v1..main` for only what's new since v1, in
+// which case whoever unbundles it needs to have v1 already. comes with an
+// etag, the ids it's of, for resuming it
+pub fn bundle(repo: &Repository, range: &str) -> Result<(String, Vec<u8>), Missing> {
+    let (from, to) = match range.find("..") {
+        Some(index) => (Some(&range[..index]), &range[index + 2..]),
+        None => (None, range),
+    };
+    let tip = resolve(repo, to)?;
+    let prerequisite = match from {
+        Some(from) => Some(resolve_commit(repo, from)?),
+        None => None,
+    };
+
+    // the pack is the slow bit and only depends on the object ids, so that's
+    // what gets cached
+    let ids = match prerequisite {
+        Some(prerequisite) => format!("{}..{}", prerequisite, tip.id),
+        None => tip.id.to_string(),
+    };
+    let key = format!("{}.pack", ids);
+    let common: Vec<git2::Oid> = prerequisite.into_iter().collect();
+    let pack = cache::get_or_make("bundle", repo, &key, || {
+        let mut pack = vec![];
+        upload_pack::pack(repo, &[tip.id], &common, &mut pack)?;
+        Ok(pack)
+    })?;
+
+    let mut header = SIGNATURE.to_string();
+    for prerequisite in &common {
+        header += &format!("-{}\n", prerequisite);
+    }
+    header += &format!("{} {}\n", tip.id, tip.refname);
+    // so `git clone` has something to check out
+    if let Some(commit) = tip.commit {
+        header += &format!("{} HEAD\n", commit);
+    }
+    header += "\n";
+
+    let mut bundle = header.into_bytes();
+    bundle.extend_from_slice(&pack);
+    Ok((format!("\"{}\"", ids), bundle))
+}
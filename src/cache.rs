@@ -0,0 +1,89 @@
+use crate::missing::Missing;
+use crate::repository::Repository;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{fs, path, process};
+
+// next to ./sock, not in the git root, or every folder in here would look
+// like a user
+static CACHE_PATH: &str = "./cache";
+
+fn get_path(kind: &str, repo: &Repository, key: &str) -> path::PathBuf {
+    let mut cache_path = path::PathBuf::from(CACHE_PATH);
+    cache_path.push(kind);
+    cache_path.push(&repo.user_name);
+    cache_path.push(&repo.name);
+    cache_path.push(key);
+    cache_path
+}
+
+// the key has to change whenever what make() makes would, so it should have
+// an object id in it
+pub fn get_or_make<F>(kind: &str, repo: &Repository, key: &str, make: F) -> Result<Vec<u8>, Missing>
+where
+    F: FnOnce() -> Result<Vec<u8>, Missing>,
+{
+    let cache_path = get_path(kind, repo, key);
+    if let Ok(cached) = fs::read(&cache_path) {
+        return Ok(cached);
+    }
+    let made = make()?;
+    // if the cache can't be written it's still worth sending what we made
+    if let Some(parent) = cache_path.parent() {
+        if fs::create_dir_all(parent).is_ok() {
+            // written somewhere else first so nobody reads half a file
+            let partial_path = cache_path.with_extension("partial");
+            if fs::write(&partial_path, &made).is_ok() {
+                fs::rename(&partial_path, &cache_path).unwrap_or_default();
+            }
+        }
+    }
+    Ok(made)
+}
+
+// so two requests making the same thing at once don't write over each other
+static PARTIALS: AtomicUsize = AtomicUsize::new(0);
+
+// for things too big to hold in memory: write() writes straight into the
+// cache, and what comes back is the path to read it from
+pub fn get_or_write<F>(
+    kind: &str,
+    repo: &Repository,
+    key: &str,
+    write: F,
+) -> Result<path::PathBuf, Missing>
+where
+    F: FnOnce(&mut fs::File) -> Result<(), Missing>,
+{
+    let cache_path = get_path(kind, repo, key);
+    if cache_path.is_file() {
+        return Ok(cache_path);
+    }
+    let parent = match cache_path.parent() {
+        Some(parent) => parent,
+        None => return Err(Missing::Nowhere),
+    };
+    if fs::create_dir_all(parent).is_err() {
+        return Err(Missing::Nowhere);
+    }
+    // written somewhere else first so nobody reads half a file
+    let partial_path = parent.join(format!(
+        "{}.{}-{}.partial",
+        key,
+        process::id(),
+        PARTIALS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = match fs::File::create(&partial_path) {
+        Ok(file) => file,
+        Err(_) => return Err(Missing::Nowhere),
+    };
+    let written = write(&mut file);
+    drop(file);
+    match written.and_then(|_| fs::rename(&partial_path, &cache_path).map_err(|_| Missing::Nowhere))
+    {
+        Ok(()) => Ok(cache_path),
+        Err(missing) => {
+            fs::remove_file(&partial_path).unwrap_or_default();
+            Err(missing)
+        }
+    }
+}
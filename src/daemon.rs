@@ -0,0 +1,85 @@
+use crate::missing::Missing;
+use crate::pkt;
+use crate::repository::Repository;
+use crate::upload_pack;
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::{thread, time};
+
+// https://git-scm.com/docs/pack-protocol#_git_transport
+
+// the first thing a client says is `git-upload-pack /user/repo.git\0host=...\0`
+// with maybe `\0version=2\0` on the end
+struct Greeting {
+    service: String,
+    path: String,
+    version: u8,
+}
+
+fn greeting(request: &[u8]) -> Result<Greeting, Missing> {
+    let (packet, _) = pkt::read(request)?;
+    let line = match packet.text() {
+        Some(line) => line,
+        None => return Err(Missing::Nowhere),
+    };
+    let mut parts = line.split('\0');
+    let mut command = parts.next().unwrap_or("").splitn(2, ' ');
+    let (service, path) = match (command.next(), command.next()) {
+        (Some(service), Some(path)) => (service.to_owned(), path.to_owned()),
+        _ => return Err(Missing::Nowhere),
+    };
+    let version = if parts.any(|part| part == "version=2") {
+        2
+    } else {
+        0
+    };
+    Ok(Greeting {
+        service,
+        path,
+        version,
+    })
+}
+
+fn open(path: &str) -> Result<Repository, Missing> {
+    let repo = Repository::open_path(path)?;
+    if repo.exported() {
+        Ok(repo)
+    } else {
+        Err(Missing::Nowhere)
+    }
+}
+
+fn handle(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(time::Duration::from_secs(60)))?;
+    let greeting = greeting(&pkt::read_raw(&mut stream)?)?;
+    let repo = match greeting.service.as_str() {
+        upload_pack::SERVICE => open(&greeting.path),
+        _ => Err(Missing::Nowhere),
+    };
+    match repo {
+        Ok(repo) => upload_pack::serve(&repo, &mut stream, greeting.version),
+        // the same thing git-daemon says, so we don't give away which repos
+        // exist
+        Err(_) => stream.write_all(&pkt::line(&format!(
+            "ERR access denied or repository not exported: {}",
+            greeting.path
+        ))),
+    }
+}
+
+pub fn listen(port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    eprintln!("Listening for git:// on port {}", port);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if let Ok(stream) = stream {
+                thread::spawn(move || {
+                    if let Err(error) = handle(stream) {
+                        eprintln!("git:// went wrong: {}", error);
+                    }
+                });
+            }
+        }
+    });
+    Ok(())
+}
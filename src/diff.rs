@@ -0,0 +1,444 @@
+use crate::highlight::DiffHighlighter;
+use std::cmp;
+use std::ops::Range;
+
+// one file's worth of a git2::Diff, with what the pages need to know about
+// it worked out up front
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    // one column, like `git diff`
+    Unified,
+    // the old file on the left and the new one on the right
+    Split,
+}
+
+impl Mode {
+    // from the `diff` query parameter
+    pub fn from_query(mode: Option<&str>) -> Mode {
+        match mode {
+            Some("split") => Mode::Split,
+            _ => Mode::Unified,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Mode::Unified => "unified",
+            Mode::Split => "split",
+        }
+    }
+}
+
+pub struct Line {
+    // ' ', '+' or '-'
+    pub origin: char,
+    pub old_lineno: Option<u32>,
+    pub new_lineno: Option<u32>,
+    pub content: String,
+    // the bits of the content that are different in the line it's paired
+    // with, as byte ranges
+    pub changed: Vec<Range<usize>>,
+    // the content as highlighted html, once `highlight` has been at it
+    pub html: Option<String>,
+}
+
+pub struct Hunk {
+    // `@@ -1,4 +1,5 @@ fn main() {`
+    pub header: String,
+    pub lines: Vec<Line>,
+}
+
+// which lines go next to each other, by index. removed lines are paired up
+// with the added lines that came after them, and whichever side runs out
+// first gets gaps
+fn pairs(lines: &[Line]) -> Vec<(Option<usize>, Option<usize>)> {
+    fn flush(
+        removed: &mut Vec<usize>,
+        added: &mut Vec<usize>,
+        pairs: &mut Vec<(Option<usize>, Option<usize>)>,
+    ) {
+        for index in 0..cmp::max(removed.len(), added.len()) {
+            pairs.push((removed.get(index).cloned(), added.get(index).cloned()));
+        }
+        removed.clear();
+        added.clear();
+    }
+
+    let mut pairs = vec![];
+    let mut removed = vec![];
+    let mut added = vec![];
+    for (index, line) in lines.iter().enumerate() {
+        match line.origin {
+            '-' => {
+                // a new run of removals
+                if !added.is_empty() {
+                    flush(&mut removed, &mut added, &mut pairs);
+                }
+                removed.push(index)
+            }
+            '+' => added.push(index),
+            _ => {
+                flush(&mut removed, &mut added, &mut pairs);
+                pairs.push((Some(index), Some(index)));
+            }
+        }
+    }
+    flush(&mut removed, &mut added, &mut pairs);
+    pairs
+}
+
+impl Hunk {
+    // the lines side by side
+    pub fn rows(&self) -> Vec<Row> {
+        pairs(&self.lines)
+            .into_iter()
+            .map(|(old, new)| {
+                (
+                    old.map(|index| &self.lines[index]),
+                    new.map(|index| &self.lines[index]),
+                )
+            })
+            .collect()
+    }
+}
+
+// the old line and the new one, for split diffs
+pub type Row<'a> = (Option<&'a Line>, Option<&'a Line>);
+
+// words, runs of spaces, and everything else one character at a time
+fn tokens(line: &str) -> Vec<Range<usize>> {
+    let kind = |character: char| {
+        if character.is_alphanumeric() || character == '_' {
+            0
+        } else if character.is_whitespace() {
+            1
+        } else {
+            2
+        }
+    };
+    let mut tokens: Vec<Range<usize>> = vec![];
+    let mut last_kind = None;
+    for (index, character) in line.char_indices() {
+        let this_kind = kind(character);
+        match tokens.last_mut() {
+            Some(token) if this_kind != 2 && last_kind == Some(this_kind) => {
+                token.end = index + character.len_utf8()
+            }
+            _ => tokens.push(index..index + character.len_utf8()),
+        }
+        last_kind = Some(this_kind);
+    }
+    tokens
+}
+
+// past this the lines are too long to be worth comparing word by word
+const MAX_TOKENS: usize = 500;
+
+// the ranges of each line that aren't in the other, found with the longest
+// common subsequence of their words. lines with nothing in common get
+// nothing, since it'd all be emphasised anyway
+fn changes(old: &str, new: &str) -> (Vec<Range<usize>>, Vec<Range<usize>>) {
+    let old_tokens = tokens(old);
+    let new_tokens = tokens(new);
+    if old_tokens.len() > MAX_TOKENS || new_tokens.len() > MAX_TOKENS {
+        return (vec![], vec![]);
+    }
+    let same = |a: usize, b: usize| old[old_tokens[a].clone()] == new[new_tokens[b].clone()];
+
+    // lengths[a][b] is the lcs of the tokens from a and b on
+    let mut lengths = vec![vec![0u16; new_tokens.len() + 1]; old_tokens.len() + 1];
+    for a in (0..old_tokens.len()).rev() {
+        for b in (0..new_tokens.len()).rev() {
+            lengths[a][b] = if same(a, b) {
+                lengths[a + 1][b + 1] + 1
+            } else {
+                cmp::max(lengths[a + 1][b], lengths[a][b + 1])
+            };
+        }
+    }
+
+    let mut old_common = vec![false; old_tokens.len()];
+    let mut new_common = vec![false; new_tokens.len()];
+    let (mut a, mut b) = (0, 0);
+    while a < old_tokens.len() && b < new_tokens.len() {
+        if same(a, b) {
+            old_common[a] = true;
+            new_common[b] = true;
+            a += 1;
+            b += 1;
+        } else if lengths[a + 1][b] >= lengths[a][b + 1] {
+            a += 1;
+        } else {
+            b += 1;
+        }
+    }
+
+    let has_words = |line: &str, tokens: &[Range<usize>], common: &[bool]| {
+        tokens
+            .iter()
+            .zip(common)
+            .any(|(token, common)| *common && !line[token.clone()].trim().is_empty())
+    };
+    if !has_words(old, &old_tokens, &old_common) {
+        return (vec![], vec![]);
+    }
+
+    let ranges = |tokens: &[Range<usize>], common: &[bool]| {
+        let mut ranges: Vec<Range<usize>> = vec![];
+        for (token, common) in tokens.iter().zip(common) {
+            if *common {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(range) if range.end == token.start => range.end = token.end,
+                _ => ranges.push(token.clone()),
+            }
+        }
+        ranges
+    };
+    (
+        ranges(&old_tokens, &old_common),
+        ranges(&new_tokens, &new_common),
+    )
+}
+
+pub struct FileDiff {
+    pub status: git2::Delta,
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub additions: usize,
+    pub deletions: usize,
+    // how alike the two files are in percent, for renames and copies
+    pub similarity: Option<u32>,
+    // binary files have no lines, so they get their sizes shown instead
+    pub binary: bool,
+    // the blobs on each side, with None for the side that doesn't exist
+    pub old_id: Option<git2::Oid>,
+    pub new_id: Option<git2::Oid>,
+    pub old_size: u64,
+    pub new_size: u64,
+    pub hunks: Vec<Hunk>,
+    // false for the files past the line limit, which never had their hunks
+    // made, so there's only the names, the status and the counts
+    pub loaded: bool,
+}
+
+fn path_string(path: Option<&std::path::Path>) -> Option<String> {
+    path.and_then(|path| path.to_str())
+        .map(|path| path.to_owned())
+}
+
+impl FileDiff {
+    // the name it has now, or had before it was deleted
+    pub fn path(&self) -> &str {
+        self.new_path
+            .as_ref()
+            .or(self.old_path.as_ref())
+            .map(|path| &**path)
+            .unwrap_or("")
+    }
+
+    // the kinds of image a browser can show, which get a before and after
+    // instead of a diff
+    pub fn is_image(&self) -> bool {
+        super::guess_mime(self.path()).type_() == mime::IMAGE
+    }
+
+    pub fn status_name(&self) -> &str {
+        match self.status {
+            git2::Delta::Added => "added",
+            git2::Delta::Deleted => "deleted",
+            git2::Delta::Renamed => "renamed",
+            git2::Delta::Copied => "copied",
+            git2::Delta::Typechange => "type changed",
+            _ => "modified",
+        }
+    }
+
+    // what the highlighter goes by: the extension, or the whole name for
+    // files like Makefile
+    fn syntax_token(&self) -> &str {
+        let path = std::path::Path::new(self.path());
+        path.extension()
+            .unwrap_or(path.file_name().unwrap_or_default())
+            .to_str()
+            .unwrap_or("")
+    }
+
+    // highlights the lines in the file's language. the old side and the new
+    // side are highlighted separately, context lines going through both, so
+    // each reads like the file it came from
+    pub fn highlight(&mut self) {
+        let first_line = self
+            .hunks
+            .first()
+            .and_then(|hunk| hunk.lines.first())
+            .map(|line| line.content.clone())
+            .unwrap_or_default();
+        let token = self.syntax_token().to_owned();
+        let mut highlighter = DiffHighlighter::new(&token, &first_line);
+        for hunk in &mut self.hunks {
+            for line in &mut hunk.lines {
+                line.html = Some(match line.origin {
+                    '-' => highlighter.removed(&line.content, &line.changed),
+                    '+' => highlighter.added(&line.content, &line.changed),
+                    _ => highlighter.context(&line.content),
+                });
+            }
+        }
+    }
+
+    // what goes in the url to jump to it
+    pub fn anchor(index: usize) -> String {
+        format!("diff-{}", index + 1)
+    }
+}
+
+// git2 doesn't give us the deltas' similarity, but it's in the raw format,
+// like `:100644 100644 1234567... 89abcde... R087`, one line per delta
+fn similarities(diff: &git2::Diff) -> Vec<Option<u32>> {
+    let mut similarities = vec![];
+    let _ = diff.print(git2::DiffFormat::Raw, |_, _, line| {
+        let raw = String::from_utf8_lossy(line.content());
+        let status = raw
+            .split('\t')
+            .next()
+            .and_then(|fields| fields.rsplit(' ').next())
+            .unwrap_or("");
+        similarities.push(match status.chars().next() {
+            Some('R') | Some('C') => status[1..].trim().parse().ok(),
+            _ => None,
+        });
+        true
+    });
+    similarities
+}
+
+fn hunks(patch: &git2::Patch) -> Vec<Hunk> {
+    let mut hunks = vec![];
+    for hunk_index in 0..patch.num_hunks() {
+        let (hunk, line_count) = match patch.hunk(hunk_index) {
+            Ok(hunk) => hunk,
+            Err(_) => continue,
+        };
+        let mut lines = vec![];
+        for line_index in 0..line_count {
+            let line = match patch.line_in_hunk(hunk_index, line_index) {
+                Ok(line) => line,
+                Err(_) => continue,
+            };
+            // leaves out the "no newline at end of file" markers
+            if !['+', '-', ' '].contains(&line.origin()) {
+                continue;
+            }
+            let content = String::from_utf8_lossy(line.content());
+            lines.push(Line {
+                origin: line.origin(),
+                old_lineno: line.old_lineno(),
+                new_lineno: line.new_lineno(),
+                content: content.trim_end_matches(&['\r', '\n'][..]).to_owned(),
+                changed: vec![],
+                html: None,
+            });
+        }
+        for pair in pairs(&lines) {
+            if let (Some(old), Some(new)) = pair {
+                if old != new {
+                    let (old_changed, new_changed) =
+                        changes(&lines[old].content, &lines[new].content);
+                    lines[old].changed = old_changed;
+                    lines[new].changed = new_changed;
+                }
+            }
+        }
+        hunks.push(Hunk {
+            header: String::from_utf8_lossy(hunk.header()).trim_end().to_owned(),
+            lines,
+        });
+    }
+    hunks
+}
+
+// the commit against one of its parents, counting from 1, or against nothing
+// for the first commit. with binary, binary files get their whole patch like
+// `git format-patch` gives them, so the text of it can be applied. only the
+// text versions want that, the page just says the file changed
+pub fn commit_diff<'repo>(
+    repo: &'repo git2::Repository,
+    commit: &git2::Commit,
+    parent_number: usize,
+    binary: bool,
+) -> Result<git2::Diff<'repo>, git2::Error> {
+    let parent_tree = match commit.parent(parent_number - 1) {
+        Ok(parent) => parent.tree().ok(),
+        Err(_) => None,
+    };
+    repo.diff_tree_to_tree(
+        parent_tree.as_ref(),
+        commit.tree().ok().as_ref(),
+        Some(git2::DiffOptions::default().show_binary(binary)),
+    )
+    .and_then(find_similar)
+}
+
+// the whole thing as `git diff` prints it
+pub fn text(diff: &git2::Diff) -> String {
+    let mut text = vec![];
+    let _ = diff.print(git2::DiffFormat::Patch, |_, _, line| {
+        if let '+' | '-' | ' ' = line.origin() {
+            text.push(line.origin() as u8);
+        }
+        text.extend_from_slice(line.content());
+        true
+    });
+    String::from_utf8_lossy(&text).into_owned()
+}
+
+// renames and copies as one file each instead of a delete and an add, like
+// `git diff -M -C`
+pub fn find_similar(mut diff: git2::Diff) -> Result<git2::Diff, git2::Error> {
+    let mut options = git2::DiffFindOptions::new();
+    options.renames(true).copies(true);
+    diff.find_similar(Some(&mut options))?;
+    Ok(diff)
+}
+
+// each file's patch, until there have been more than max_lines lines of
+// them. the ones after that aren't going to be shown, so they only get their
+// counts and not their hunks
+pub fn files(diff: &git2::Diff, max_lines: usize) -> Vec<FileDiff> {
+    let mut files = vec![];
+    let mut lines = 0;
+    let similarities = similarities(diff);
+    let blob_id = |file: git2::DiffFile| Some(file.id()).filter(|id| !id.is_zero());
+    for (index, delta) in diff.deltas().enumerate() {
+        let similarity = similarities.get(index).cloned().flatten();
+        let patch = match git2::Patch::from_diff(diff, index) {
+            Ok(Some(patch)) => patch,
+            _ => continue,
+        };
+        let (context, additions, deletions) = patch.line_stats().unwrap_or((0, 0, 0));
+        let shown = lines <= max_lines;
+        lines += context + additions + deletions;
+        // the patch has the sizes and binaryness, which the diff doesn't
+        // know until the blobs are loaded
+        let loaded = patch.delta();
+        files.push(FileDiff {
+            status: delta.status(),
+            old_path: path_string(delta.old_file().path()),
+            new_path: path_string(delta.new_file().path()),
+            additions,
+            deletions,
+            similarity,
+            binary: loaded.flags().is_binary(),
+            old_id: blob_id(loaded.old_file()),
+            new_id: blob_id(loaded.new_file()),
+            old_size: loaded.old_file().size(),
+            new_size: loaded.new_file().size(),
+            hunks: if shown { hunks(&patch) } else { vec![] },
+            loaded: shown,
+        });
+    }
+    files
+}
@@ -1,6 +1,7 @@
+use std::ops::Range;
 use syntect::dumps::from_binary;
 use syntect::easy::HighlightLines;
-use syntect::highlighting::ThemeSet;
+use syntect::highlighting::{Style, ThemeSet};
 use syntect::html::{
     append_highlighted_html_for_styled_line, start_highlighted_html_snippet, IncludeBackground,
 };
@@ -40,23 +41,123 @@ pub fn get_syntax<'string>(token: &str, string: &'string str) -> &'string Syntax
 //     }
 //     html_generator.finalize()
 
-pub fn highlight(token: &str, string: &str) -> String {
+// each line on its own, for pages that put other things next to them
+pub fn highlight_lines(token: &str, string: &str) -> Vec<String> {
     let syntax = get_syntax(token, string);
     let theme = &THEME_SET.themes["lychee"];
     let mut highlighter = HighlightLines::new(syntax, theme);
     let (_, bg) = start_highlighted_html_snippet(theme);
-    let mut output = String::from("<ol class=\"blob-content__lines\">");
+    let mut lines = vec![];
 
     for line in LinesWithEndings::from(string) {
         let regions = highlighter.highlight(line, &SYNTAX_SET);
-        output += "<li class=\"blob-content__line\">";
+        let mut output = String::new();
         append_highlighted_html_for_styled_line(
             &regions[..],
             IncludeBackground::IfDifferent(bg),
             &mut output,
         );
+        lines.push(output);
+    }
+
+    lines
+}
+
+pub fn highlight(token: &str, string: &str) -> String {
+    let mut output = String::from("<ol class=\"blob-content__lines\">");
+
+    for line in highlight_lines(token, string) {
+        output += "<li class=\"blob-content__line\">";
+        output += &line;
     }
 
     output += "</ol>";
     output
 }
+
+// highlights the two sides of a file's diff, each one carrying on from its
+// own last line so strings and comments that span lines come out right
+pub struct DiffHighlighter<'a> {
+    old: HighlightLines<'a>,
+    new: HighlightLines<'a>,
+}
+
+// the line's html, without the background so the diff's shows through, and
+// with the changed ranges wrapped so they can be picked out
+fn diff_line_html(regions: &[(Style, &str)], length: usize, changed: &[Range<usize>]) -> String {
+    let mut output = String::new();
+    let mut offset = 0;
+    let mut in_change = false;
+    for (style, text) in regions {
+        let start = offset;
+        offset += text.len();
+        let end = std::cmp::min(offset, length);
+        let mut from = start;
+        while from < end {
+            // where this piece stops being changed or unchanged
+            let changing = changed
+                .iter()
+                .find(|range| range.start <= from && from < range.end);
+            let to = match changing {
+                Some(range) => std::cmp::min(range.end, end),
+                None => changed
+                    .iter()
+                    .map(|range| range.start)
+                    .filter(|range_start| *range_start > from)
+                    .min()
+                    .map_or(end, |range_start| std::cmp::min(range_start, end)),
+            };
+            // one span for the whole change, even when it covers a few
+            // differently coloured bits
+            if changing.is_some() != in_change {
+                in_change = changing.is_some();
+                output += if in_change {
+                    "<span class=\"diff-table__change\">"
+                } else {
+                    "</span>"
+                };
+            }
+            let piece = &text[from - start..to - start];
+            append_highlighted_html_for_styled_line(
+                &[(*style, piece)],
+                IncludeBackground::No,
+                &mut output,
+            );
+            from = to;
+        }
+    }
+    if in_change {
+        output += "</span>";
+    }
+    output
+}
+
+impl DiffHighlighter<'_> {
+    // first_line helps pick the syntax when the extension doesn't
+    pub fn new<'a>(token: &str, first_line: &'a str) -> DiffHighlighter<'a> {
+        let syntax = get_syntax(token, first_line);
+        let theme = &THEME_SET.themes["lychee"];
+        DiffHighlighter {
+            old: HighlightLines::new(syntax, theme),
+            new: HighlightLines::new(syntax, theme),
+        }
+    }
+
+    pub fn removed(&mut self, content: &str, changed: &[Range<usize>]) -> String {
+        let line = format!("{}\n", content);
+        let regions = self.old.highlight(&line, &SYNTAX_SET);
+        diff_line_html(&regions, content.len(), changed)
+    }
+
+    pub fn added(&mut self, content: &str, changed: &[Range<usize>]) -> String {
+        let line = format!("{}\n", content);
+        let regions = self.new.highlight(&line, &SYNTAX_SET);
+        diff_line_html(&regions, content.len(), changed)
+    }
+
+    // lines on both sides, which have to go through both to keep them going
+    pub fn context(&mut self, content: &str) -> String {
+        self.removed(content, &[]);
+        self.added(content, &[])
+    }
+}
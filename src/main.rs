@@ -1,4 +1,5 @@
 #![feature(proc_macro_hygiene)]
+extern crate base64;
 extern crate chrono_humanize;
 extern crate futures;
 extern crate git2;
@@ -7,24 +8,47 @@ extern crate hyperlocal;
 #[macro_use]
 extern crate lazy_static;
 extern crate comrak;
+extern crate flate2;
 extern crate maud;
 extern crate mime;
+extern crate sha2;
 extern crate syntect;
+extern crate tar;
+extern crate thrussh;
+extern crate thrussh_keys;
 extern crate typed_arena;
+extern crate zip;
 
-use crate::futures::Future;
+use crate::futures::{Future, Sink, Stream};
+use flate2::read::GzDecoder;
+use futures::future::Either;
 use hyper::service::service_fn;
-use hyper::{header, Body, Request, Response, StatusCode};
+use hyper::{header, Body, Method, Request, Response, StatusCode};
 use maud::{html, Markup};
 use missing::Missing;
-use std::{env, fs, io, path};
+use std::io::{Read, Seek};
+use std::{env, fs, io, path, thread};
 
+mod archive;
+mod attributes;
+mod blame;
+mod bundle;
+mod cache;
+mod daemon;
+mod diff;
 mod highlight;
 mod markup;
+mod mbox;
+mod mirror;
 mod missing;
 mod page;
+mod pkt;
+mod receive_pack;
 mod repository;
+mod shallow;
+mod ssh;
 mod tree;
+mod upload_pack;
 mod user;
 
 pub enum ContentType {
@@ -33,6 +57,133 @@ pub enum ContentType {
     // markup, optional title
     Markup(Markup, Option<String>),
     PlainText(String),
+    // content_type, data, a strong etag for it, and the Range header if
+    // there was one and it was for this version of data
+    Resumable(String, Download, String, Option<String>),
+    // content_type, and something to write the body as it's made
+    Streamed(String, Writer),
+}
+
+// what a resumable download is sent from
+pub enum Download {
+    Bytes(Vec<u8>),
+    // already all there, like something in the cache, so it can be sent a
+    // bit at a time
+    File(path::PathBuf),
+}
+
+impl Download {
+    fn len(&self) -> Result<usize, Missing> {
+        match self {
+            Download::Bytes(bytes) => Ok(bytes.len()),
+            Download::File(path) => match fs::metadata(path) {
+                Ok(metadata) => Ok(metadata.len() as usize),
+                Err(_) => Err(Missing::Nowhere),
+            },
+        }
+    }
+
+    // length bytes of it from start
+    fn body(self, start: usize, length: usize) -> Result<Body, Missing> {
+        match self {
+            Download::Bytes(bytes) => Ok(Body::from(bytes[start..start + length].to_vec())),
+            Download::File(path) => {
+                let mut file = match fs::File::open(path) {
+                    Ok(file) => file,
+                    Err(_) => return Err(Missing::Nowhere),
+                };
+                if length == 0 {
+                    return Ok(Body::empty());
+                }
+                if file.seek(io::SeekFrom::Start(start as u64)).is_err() {
+                    return Err(Missing::Nowhere);
+                }
+                stream(Box::new(move |output| {
+                    match io::copy(&mut file.take(length as u64), output) {
+                        Ok(_) => Ok(()),
+                        Err(_) => Err(Missing::Nowhere),
+                    }
+                }))
+            }
+        }
+    }
+}
+
+pub type Writer = Box<dyn FnOnce(&mut dyn io::Write) -> Result<(), Missing> + Send>;
+
+// how many writes can be waiting to go out before the writer has to wait
+const STREAM_BUFFER: usize = 16;
+
+// hands each write to the response body as it happens
+struct BodyWriter {
+    sender: futures::sink::Wait<futures::sync::mpsc::Sender<Result<Vec<u8>, Missing>>>,
+}
+
+impl io::Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.sender.send(Ok(buf.to_vec())) {
+            Ok(_) => Ok(buf.len()),
+            Err(_) => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// runs the writer on its own thread, so the body goes out while it's being
+// made rather than all at the end. it waits for the first thing written, so
+// anything that goes wrong before then still gets its proper status
+fn stream(write: Writer) -> Result<Body, Missing> {
+    let (sender, receiver) = futures::sync::mpsc::channel(STREAM_BUFFER);
+    thread::spawn(move || {
+        let mut output = BodyWriter {
+            sender: sender.wait(),
+        };
+        if let Err(missing) = write(&mut output) {
+            output.sender.send(Err(missing)).unwrap_or_default();
+        }
+    });
+    let (first, rest) = match receiver.into_future().wait() {
+        Ok(received) => received,
+        Err(_) => return Err(Missing::Nowhere),
+    };
+    let first = match first {
+        Some(first) => first?,
+        None => return Ok(Body::empty()),
+    };
+    // going wrong after that just cuts the response off
+    let rest = rest.then(|chunk| match chunk {
+        Ok(Ok(chunk)) => Ok(chunk),
+        Ok(Err(missing)) => Err(io::Error::from(missing)),
+        Err(_) => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+    });
+    Ok(Body::wrap_stream(
+        futures::stream::once(Ok(first)).chain(rest),
+    ))
+}
+
+// only the one range, `bytes=start-`, `bytes=start-end` or `bytes=-suffix`,
+// which is all anybody resuming a download asks for
+fn get_range(range: &str, length: usize) -> Option<(usize, usize)> {
+    if !range.starts_with("bytes=") || range.contains(',') {
+        return None;
+    }
+    let mut bounds = range["bytes=".len()..].splitn(2, '-');
+    let (start, end) = match (bounds.next(), bounds.next()) {
+        (Some(start), Some(end)) => (start.trim(), end.trim()),
+        _ => return None,
+    };
+    let last = length.saturating_sub(1);
+    match (start.parse::<usize>(), end.parse::<usize>()) {
+        (Ok(start), Ok(end)) if start <= end => Some((start, end.min(last))),
+        (Ok(start), Err(_)) if end.is_empty() => Some((start, last)),
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => {
+            Some((length.saturating_sub(suffix), last))
+        }
+        _ => None,
+    }
 }
 
 fn respond(content: Result<ContentType, Missing>) -> Response<Body> {
@@ -67,6 +218,50 @@ fn respond(content: Result<ContentType, Missing>) -> Response<Body> {
                 .expect("Failed to construct the response");
             response
         }
+        Ok(ContentType::Resumable(content_type, download, etag, range)) => {
+            let length = match download.len() {
+                Ok(length) => length,
+                Err(missing) => return respond(Err(missing)),
+            };
+            let range = range.and_then(|range| get_range(&range, length));
+            let mut response = Response::builder();
+            response
+                .header(header::ACCEPT_RANGES, "bytes")
+                .header(header::ETAG, etag)
+                .header(header::CONTENT_TYPE, content_type);
+            let response = match range {
+                None => match download.body(0, length) {
+                    Ok(body) => response
+                        .header(header::CONTENT_LENGTH, length as u64)
+                        .body(body),
+                    Err(missing) => return respond(Err(missing)),
+                },
+                Some((start, end)) if start < length => match download.body(start, end - start + 1)
+                {
+                    Ok(body) => response
+                        .status(StatusCode::PARTIAL_CONTENT)
+                        .header(
+                            header::CONTENT_RANGE,
+                            format!("bytes {}-{}/{}", start, end, length),
+                        )
+                        .header(header::CONTENT_LENGTH, (end - start + 1) as u64)
+                        .body(body),
+                    Err(missing) => return respond(Err(missing)),
+                },
+                Some(_) => response
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(header::CONTENT_RANGE, format!("bytes */{}", length))
+                    .body(Body::empty()),
+            };
+            response.expect("Failed to construct the response")
+        }
+        Ok(ContentType::Streamed(content_type, write)) => match stream(write) {
+            Ok(body) => Response::builder()
+                .header(header::CONTENT_TYPE, content_type)
+                .body(body)
+                .expect("Failed to construct the response"),
+            Err(missing) => respond(Err(missing)),
+        },
         Err(missing) => {
             eprintln!("missing {:?}", missing);
             let response = match missing {
@@ -83,6 +278,19 @@ fn respond(content: Result<ContentType, Missing>) -> Response<Body> {
                     .status(StatusCode::FOUND)
                     .body(Body::from("sorry"))
                     .expect("failed to redirect"),
+                Missing::Unwelcome => Response::builder()
+                    .header(header::WWW_AUTHENTICATE, "Basic realm=\"snootforge\"")
+                    .status(StatusCode::UNAUTHORIZED)
+                    .body(Body::from("who are you?"))
+                    .expect("failed"),
+                Missing::Garbled => Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("what?"))
+                    .expect("failed"),
+                Missing::TooBig => Response::builder()
+                    .status(StatusCode::PAYLOAD_TOO_LARGE)
+                    .body(Body::from("that's too much"))
+                    .expect("failed"),
             };
             response
         }
@@ -91,7 +299,7 @@ fn respond(content: Result<ContentType, Missing>) -> Response<Body> {
 fn get_git_root() -> path::PathBuf {
     let args = get_args();
     let mut pathbuf = path::PathBuf::new();
-    pathbuf.push(args.get(1).expect("Usage: yeet <root_path>"));
+    pathbuf.push(args.get(1).expect(USAGE));
     pathbuf
 }
 
@@ -172,16 +380,166 @@ fn get_static_file(file_name: &str) -> Option<Response<Body>> {
     }
 }
 
+// decoded, since values like directory names can have anything in them
+pub fn get_query_param(query: Option<&str>, key: &str) -> Option<String> {
+    for pair in query.unwrap_or("").split('&') {
+        let mut pair = pair.splitn(2, '=');
+        if pair.next() == Some(key) {
+            return Some(decode_query_value(pair.next().unwrap_or("")));
+        }
+    }
+    None
+}
+
+fn decode_query_value(value: &str) -> String {
+    let value = value.as_bytes();
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut index = 0;
+    while index < value.len() {
+        let hex = value
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (value[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// what get_query_param will decode back to value. slashes are left alone so
+// paths still read like paths
+pub fn encode_query_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for &byte in value.as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded += &format!("%{:02X}", byte),
+        }
+    }
+    encoded
+}
+
+fn get_uri_parts(uri_path: &path::PathBuf) -> Vec<&str> {
+    uri_path
+        .components()
+        .skip(1)
+        .map(|component: path::Component| component.as_os_str().to_str().unwrap())
+        .collect()
+}
+
+// the most of a request body we'll hold in memory, before or after it's
+// decoded. pushes are the big ones, and this is a big push
+const MAX_BODY_SIZE: usize = 512 * 1024 * 1024;
+
+fn decode_body(body: Vec<u8>, content_encoding: Option<&str>) -> Result<Vec<u8>, Missing> {
+    match content_encoding {
+        Some("gzip") | Some("x-gzip") => {
+            let mut decoded = vec![];
+            // one more than the limit, to tell a body that's exactly the
+            // limit from one that's over it
+            let mut decoder = GzDecoder::new(&body[..]).take(MAX_BODY_SIZE as u64 + 1);
+            match decoder.read_to_end(&mut decoded) {
+                Ok(_) if decoded.len() > MAX_BODY_SIZE => Err(Missing::TooBig),
+                Ok(_) => Ok(decoded),
+                Err(_) => Err(Missing::Garbled),
+            }
+        }
+        _ => Ok(body),
+    }
+}
+
+fn get_header(request: &Request<Body>, name: &str) -> Option<String> {
+    request
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_owned())
+}
+
+fn route_post(
+    uri_path: path::PathBuf,
+    body: Vec<u8>,
+    git_protocol: Option<String>,
+    authorization: Option<String>,
+) -> Response<Body> {
+    let uri_parts = get_uri_parts(&uri_path);
+    match uri_parts.len() {
+        3 => {
+            let user_name = uri_parts.get(0).unwrap();
+            let project_name = uri_parts.get(1).unwrap();
+            let project_name = if project_name.ends_with(".git") {
+                &project_name[..project_name.len() - 4]
+            } else {
+                *project_name
+            };
+            match uri_parts[2] {
+                "git-upload-pack" => respond(page::upload_pack(
+                    user_name,
+                    project_name,
+                    body,
+                    git_protocol.as_ref().map(|p| &**p),
+                )),
+                "git-receive-pack" => respond(page::receive_pack(
+                    user_name,
+                    project_name,
+                    &body,
+                    authorization.as_ref().map(|a| &**a),
+                )),
+                _ => respond(Err(Missing::Nowhere)),
+            }
+        }
+        _ => respond(Err(Missing::Nowhere)),
+    }
+}
+
 fn route(
     request: Request<Body>,
 ) -> impl futures::Future<Item = Response<Body>, Error = io::Error> + Send {
     let uri_path = path::PathBuf::from(request.uri().path());
-    println!("{}", request.uri());
-    let uri_parts: Vec<&str> = uri_path
-        .components()
-        .map(|component: path::Component| component.as_os_str().to_str().unwrap())
-        .collect();
-    let uri_parts = &uri_parts[1..];
+    println!("{} {}", request.method(), request.uri());
+    if *request.method() == Method::POST {
+        let content_encoding = get_header(&request, header::CONTENT_ENCODING.as_str());
+        let git_protocol = get_header(&request, "git-protocol");
+        let authorization = get_header(&request, header::AUTHORIZATION.as_str());
+        // a body that breaks off halfway is as good as garbled
+        return Either::A(
+            request
+                .into_body()
+                .map_err(|_| Missing::Garbled)
+                .fold(vec![], |mut body, chunk| {
+                    body.extend_from_slice(&chunk);
+                    if body.len() > MAX_BODY_SIZE {
+                        Err(Missing::TooBig)
+                    } else {
+                        Ok(body)
+                    }
+                })
+                .and_then(move |body| decode_body(body, content_encoding.as_ref().map(|e| &**e)))
+                .then(move |body| {
+                    Ok(match body {
+                        Ok(body) => route_post(uri_path, body, git_protocol, authorization),
+                        Err(missing) => respond(Err(missing)),
+                    })
+                }),
+        );
+    }
+    let query = request.uri().query();
+    let git_protocol = get_header(&request, "git-protocol");
+    let authorization = get_header(&request, header::AUTHORIZATION.as_str());
+    let range = get_header(&request, header::RANGE.as_str());
+    let if_range = get_header(&request, header::IF_RANGE.as_str());
+    let uri_parts = get_uri_parts(&uri_path);
+    let uri_parts = &uri_parts[..];
     let response = match uri_parts.len() {
         0 => respond(page::root()),
         1 => {
@@ -226,34 +584,135 @@ fn route(
 
             match *page_name {
                 "tree" => respond(page::tree(user_name, project_name, target, rest)),
-                "log" => respond(page::log(user_name, project_name, target, rest)),
+                "log" => respond(page::log(
+                    user_name,
+                    project_name,
+                    target,
+                    rest,
+                    get_query_param(query, "after").as_ref().map(|p| &**p),
+                    get_query_param(query, "limit").as_ref().map(|p| &**p),
+                )),
                 "blob" => respond(page::blob(user_name, project_name, target, rest)),
-                "commit" => respond(page::commit(user_name, project_name, target, rest)),
+                "blame" => respond(page::blame(user_name, project_name, target, rest)),
+                "commit" => respond(page::commit(
+                    user_name,
+                    project_name,
+                    target,
+                    rest,
+                    get_query_param(query, "parent").as_ref().map(|p| &**p),
+                    get_query_param(query, "diff").as_ref().map(|p| &**p),
+                )),
+                "compare" => respond(page::compare(
+                    user_name,
+                    project_name,
+                    target,
+                    rest,
+                    get_query_param(query, "diff").as_ref().map(|p| &**p),
+                )),
+                "mbox" => respond(page::mbox(user_name, project_name, target, rest)),
                 "refs" => respond(page::refs(user_name, project_name, target, rest)),
                 "raw" => respond(page::raw(user_name, project_name, target, rest)),
-                "info" => respond(page::info(user_name, project_name, target, rest)),
+                "blobs" => respond(page::diff_blob(
+                    user_name,
+                    project_name,
+                    target,
+                    rest,
+                    get_query_param(query, "parent").as_ref().map(|p| &**p),
+                )),
+                "info" => respond(page::info(
+                    user_name,
+                    project_name,
+                    target,
+                    rest,
+                    get_query_param(query, "service").as_ref().map(|p| &**p),
+                    git_protocol.as_ref().map(|p| &**p),
+                    authorization.as_ref().map(|a| &**a),
+                )),
+                "archive" => respond(page::archive(
+                    user_name,
+                    project_name,
+                    target,
+                    rest,
+                    get_query_param(query, "path").as_ref().map(|p| &**p),
+                    range.as_ref().map(|r| &**r),
+                    if_range.as_ref().map(|r| &**r),
+                )),
+                "bundle" => respond(page::bundle(
+                    user_name,
+                    project_name,
+                    target,
+                    rest,
+                    range.as_ref().map(|r| &**r),
+                    if_range.as_ref().map(|r| &**r),
+                )),
                 "HEAD" => respond(page::head(user_name, project_name, target, rest)),
                 "objects" => respond(page::objects(user_name, project_name, target, rest)),
                 _ => respond(Err(Missing::Nowhere)),
             }
         }
     };
-    futures::future::ok(response)
+    Either::B(futures::future::ok(response))
 }
 
+const USAGE: &str = "Usage: yeet <root_path> [<port>] [--git-port <port>] [--ssh-port <port>]";
+
+// the arguments that aren't flags, or the values that go with them
 fn get_args() -> Vec<String> {
-    let args: Vec<String> = env::args().collect();
+    let mut args = vec![];
+    let mut all = env::args();
+    while let Some(arg) = all.next() {
+        if arg.starts_with("--") {
+            all.next();
+        } else {
+            args.push(arg);
+        }
+    }
     args
 }
 
+fn parse_port(port: &str) -> Result<u16, io::Error> {
+    port.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} isn't a port\n{}", port, USAGE),
+        )
+    })
+}
+
+// the port after a flag like `--git-port`, if it's there
+fn get_port_flag(flag: &str) -> Result<Option<u16>, io::Error> {
+    let mut args = env::args().skip_while(|arg| arg != flag);
+    if args.next().is_none() {
+        return Ok(None);
+    }
+    match args.next() {
+        Some(port) => parse_port(&port).map(Some),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} needs a port\n{}", flag, USAGE),
+        )),
+    }
+}
+
 fn main() -> Result<(), io::Error> {
     let args = get_args();
 
     let port = args.get(2);
 
+    mirror::schedule();
+
+    // git:// and ssh are each only there if they're asked for
+    if let Some(git_port) = get_port_flag("--git-port")? {
+        daemon::listen(git_port)?;
+    }
+
+    if let Some(ssh_port) = get_port_flag("--ssh-port")? {
+        ssh::listen(ssh_port)?;
+    }
+
     match port {
         Some(port) => {
-            let port: u16 = port.parse().unwrap_or(3000);
+            let port = parse_port(port)?;
             let addr = ([127, 0, 0, 1], port).into();
             let server = hyper::Server::bind(&addr).serve(|| service_fn(route));
             eprintln!("Listening on localhost:{}", port);
@@ -1,4 +1,8 @@
+use crate::archive;
+use crate::blame;
+use crate::diff;
 use crate::highlight;
+use crate::mirror;
 use crate::page::Page;
 use crate::repository::Repository;
 use crate::tree::{Tree, TreeEntry, TreeEntryKind};
@@ -160,11 +164,33 @@ pub fn project_header(repo: &Repository, page: &Page) -> Markup {
                 (description)
             }
         }
+        @if let Some(mirror) = mirror::get(repo) {
+            p.project-mirror {
+                "mirrored from "
+                span.project-mirror__url {
+                    (mirror.display_url())
+                }
+                @match mirror.synced {
+                    Some(synced) => {
+                        ", last synced "
+                        (time(&synced, "project-mirror__synced"))
+                    },
+                    None => ", not synced yet",
+                }
+            }
+        }
         (project_nav(repo, page))
     )
 }
 
-pub fn blob_header(directory: &str, raw_url: &str, directory_url: &str, file_name: &str) -> Markup {
+// views are the labels and urls of the other pages about this file
+pub fn blob_header(
+    directory: &str,
+    raw_url: &str,
+    directory_url: &str,
+    file_name: &str,
+    views: &[(&str, &str)],
+) -> Markup {
     let open_folder_icon = maud::PreEscaped("&#x1f4c2;");
     html! {
         header.blob-header {
@@ -190,8 +216,15 @@ pub fn blob_header(directory: &str, raw_url: &str, directory_url: &str, file_nam
                         (file_name)
                     }
                 }
-                a.blob-header__raw href=(raw_url) {
-                    "Raw"
+                span.blob-header__views {
+                    @for (label, url) in views {
+                        a.blob-header__raw href=(url) {
+                            (label)
+                        }
+                    }
+                    a.blob-header__raw href=(raw_url) {
+                        "Raw"
+                    }
                 }
             }
         }
@@ -327,6 +360,28 @@ fn tree_entry(entry: &TreeEntry, repo_url: &str) -> Markup {
     }
 }
 
+// directory is where in the tree we are, if it's not the top
+pub fn tree_header(tree: &Tree, directory: Option<&str>) -> Markup {
+    let log_url = match directory {
+        Some(directory) => format!("{}/log/{}/{}", tree.repo_url, tree.refname, directory),
+        None => format!("{}/log/{}", tree.repo_url, tree.refname),
+    };
+    html! {
+        p.tree-archive {
+            "download " (tree.refname)
+            @if let Some(directory) = directory {
+                ":" (directory)
+            }
+            " as "
+            (archive_links(&tree.refname, &tree.repo_url, directory, false))
+            " or see its "
+            a.tree-history href=(log_url) {
+                "history"
+            }
+        }
+    }
+}
+
 pub fn tree(tree: &Tree, _page: &Page) -> Markup {
     let open_folder_icon = maud::PreEscaped("&#x1f4c2;");
     html! {
@@ -350,6 +405,41 @@ pub fn tree(tree: &Tree, _page: &Page) -> Markup {
     }
 }
 
+// lines is every line of the file, highlighted
+pub fn blame(hunks: &[blame::Hunk], lines: &[String], repo_url: &str) -> Markup {
+    html! {
+        pre.blob-content.blame {
+            @for hunk in hunks {
+                @let commit_url = format!("{}/commit/{}", repo_url, hunk.commit.id());
+                @let short_id = hunk.commit.as_object().short_id().unwrap_or_default();
+                @let date = Utc.timestamp(hunk.commit.time().seconds(), 0);
+                @let lines = lines.get(hunk.start - 1..hunk.start - 1 + hunk.lines).unwrap_or(&[]);
+                div.blame-hunk {
+                    div.blame-hunk__commit {
+                        a.commit-id.blame-hunk__id href=(commit_url) title=(hunk.commit.summary().unwrap_or("")) {
+                            (short_id.as_str().unwrap_or_default())
+                        }
+                        " "
+                        span.blame-hunk__author {
+                            (hunk.commit.author().name().unwrap_or("secret person"))
+                        }
+                        " "
+                        (time(&date, "blame-hunk__date"))
+                    }
+                    // carries the line numbers on from the hunk before
+                    ol.blob-content__lines style=(format!("counter-reset: blob-line {}", hunk.start - 1)) {
+                        @for line in lines {
+                            li.blob-content__line {
+                                (maud::PreEscaped(line))
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
 fn log_commit(commit: git2::Commit, repo_url: &str) -> Markup {
     let summary = commit.summary().unwrap_or("yeet");
     let committer = commit.committer();
@@ -407,12 +497,79 @@ pub fn log(log: Vec<git2::Commit>, repo_url: String, _page: &Page) -> Markup {
     }
 }
 
-fn refname(refname: &str, repo_url: &str) -> Markup {
+// newer and older pages of the log. url is the page without the query, and
+// limit is only there when it was asked for
+pub fn log_pages(
+    url: &str,
+    previous: Option<Option<git2::Oid>>,
+    next: Option<git2::Oid>,
+    limit: Option<usize>,
+) -> Markup {
+    let page_url = |after: Option<git2::Oid>| {
+        let url = match after {
+            Some(after) => with_query(url, "after", &after.to_string()),
+            None => url.to_owned(),
+        };
+        match limit {
+            Some(limit) => with_query(&url, "limit", &limit.to_string()),
+            None => url,
+        }
+    };
+    html! {
+        @if previous.is_some() || next.is_some() {
+            nav.log-pages {
+                @if let Some(previous) = previous {
+                    a.log-pages__link.log-pages__link--newer href=(page_url(previous)) {
+                        "← newer"
+                    }
+                }
+                @if let Some(next) = next {
+                    a.log-pages__link.log-pages__link--older href=(page_url(Some(next))) {
+                        "older →"
+                    }
+                }
+            }
+        }
+    }
+}
+
+// directory is for an archive of just that part of the tree. checksums are
+// for releases, where people want to pin the hash
+pub fn archive_links(
+    refname: &str,
+    repo_url: &str,
+    directory: Option<&str>,
+    checksums: bool,
+) -> Markup {
+    let query = match directory {
+        Some(directory) => format!("?path={}", crate::encode_query_value(directory)),
+        None => "".to_string(),
+    };
+    html! {
+        span.archive-links {
+            @for format in archive::FORMATS.iter() {
+                a.archive-links__anchor
+                    href=(format!("{}/archive/{}{}{}", repo_url, refname, format.extension(), query)) {
+                    (format.extension())
+                }
+                @if checksums {
+                    a.archive-links__anchor.archive-links__anchor--checksum
+                        href=(format!("{}/archive/{}{}.sha256{}", repo_url, refname, format.extension(), query)) {
+                        ("sha256")
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn refname(refname: &str, repo_url: &str, checksums: bool) -> Markup {
     html! {
         li.refs-name {
             a.refs-name__anchor href=(format!("{}/tree/{}", repo_url, refname)) {
                 (refname)
             }
+            (archive_links(refname, repo_url, None, checksums))
         }
     }
 }
@@ -426,7 +583,7 @@ pub fn refs(refs: (Vec<String>, Vec<String>), repo_url: String, _page: &Page) ->
                 }
                 ul.refs-list {
                     @for tag in refs.0 {
-                        (refname(&tag, &repo_url))
+                        (refname(&tag, &repo_url, true))
                     }
                 }
             }
@@ -436,7 +593,182 @@ pub fn refs(refs: (Vec<String>, Vec<String>), repo_url: String, _page: &Page) ->
                 }
                 ul.refs-list {
                     @for branch in refs.1 {
-                        (refname(&branch, &repo_url))
+                        (refname(&branch, &repo_url, false))
+                    }
+                }
+            }
+        }
+    }
+}
+
+// the line, with the parts that changed from the line it's paired with
+// picked out
+fn diff_line(line: &diff::Line) -> Markup {
+    if let Some(html) = &line.html {
+        return html! {
+            (maud::PreEscaped(html))
+        };
+    }
+    let mut parts = vec![];
+    let mut from = 0;
+    for range in &line.changed {
+        parts.push((&line.content[from..range.start], false));
+        parts.push((&line.content[range.clone()], true));
+        from = range.end;
+    }
+    parts.push((&line.content[from..], false));
+    html! {
+        @for (text, changed) in parts {
+            @if changed {
+                span.diff-table__change {
+                    (text)
+                }
+            } @else {
+                (text)
+            }
+        }
+    }
+}
+
+fn diff_line_kind(line: &diff::Line) -> &str {
+    match line.origin {
+        '-' => "removed",
+        '+' => "added",
+        _ => "context",
+    }
+}
+
+// one side of a split diff's row: the line number and the line
+fn split_side(line: Option<&diff::Line>, old: bool) -> Markup {
+    html! {
+        @match line {
+            Some(line) => {
+                @let lineno = if old { line.old_lineno } else { line.new_lineno };
+                td.diff-table__number {
+                    (lineno.map(|lineno| lineno.to_string()).unwrap_or_default())
+                }
+                td class=(format!("diff-table__line diff-table__line--{}", diff_line_kind(line))) {
+                    (diff_line(line))
+                }
+            },
+            None => {
+                td.diff-table__number {}
+                td.diff-table__line.diff-table__line--empty {}
+            },
+        }
+    }
+}
+
+fn is_moved(file: &diff::FileDiff) -> bool {
+    file.status == git2::Delta::Renamed || file.status == git2::Delta::Copied
+}
+
+fn diff_file_header(file: &diff::FileDiff) -> Markup {
+    html! {
+        header.diff-file__header {
+            @if is_moved(file) {
+                (file.old_path.as_ref().map(|p| &**p).unwrap_or("")) " → "
+            }
+            (file.path())
+            @if let Some(similarity) = file.similarity {
+                span.diff-file__similarity {
+                    (file.status_name()) ", " (similarity) "% similar"
+                }
+            }
+        }
+        // a file that was only moved has nothing to show
+        @if is_moved(file) && file.hunks.is_empty() {
+            p.diff-file__unchanged {
+                "no changes to the contents"
+            }
+        }
+    }
+}
+
+// 1.5 KB and such
+fn file_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        format!("{} bytes", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+// where to get a side of a diff's file from, "old" or "new", and its path on
+// that side
+pub type BlobUrl<'a> = &'a dyn Fn(&str, &str) -> String;
+
+// binary files get their sizes, and images get shown side by side
+fn diff_file_binary(file: &diff::FileDiff, blob_url: BlobUrl) -> Markup {
+    let name = file.path().rsplit('/').next().unwrap_or("");
+    // nothing for the side the file isn't on
+    let before = match (file.old_id, &file.old_path) {
+        (Some(_), Some(path)) => Some(blob_url("old", path)),
+        _ => None,
+    };
+    let after = match (file.new_id, &file.new_path) {
+        (Some(_), Some(path)) => Some(blob_url("new", path)),
+        _ => None,
+    };
+    html! {
+        @if file.binary {
+            p.diff-file__binary {
+                "binary file changed ("
+                @if file.old_id.is_some() {
+                    (file_size(file.old_size))
+                } @else {
+                    "nothing"
+                }
+                " → "
+                @if file.new_id.is_some() {
+                    (file_size(file.new_size))
+                } @else {
+                    "nothing"
+                }
+                ")"
+            }
+        }
+        @if file.is_image() && file.old_id != file.new_id {
+            .diff-images {
+                @for (side, url) in &[("before", before), ("after", after)] {
+                    figure class=(format!("diff-images__image diff-images__image--{}", side)) {
+                        @if let Some(url) = url {
+                            img src=(url) alt=(format!("{} {}", name, side));
+                        }
+                        figcaption {
+                            (side)
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn diff_file(file: &diff::FileDiff) -> Markup {
+    html! {
+        @if !file.hunks.is_empty() {
+            table.diff-table.diff-table--unified {
+                @for hunk in &file.hunks {
+                    tr.diff-table__hunk {
+                        td colspan="3" {
+                            (hunk.header)
+                        }
+                    }
+                    @for line in &hunk.lines {
+                        tr {
+                            td.diff-table__number {
+                                (line.old_lineno.map(|lineno| lineno.to_string()).unwrap_or_default())
+                            }
+                            td.diff-table__number {
+                                (line.new_lineno.map(|lineno| lineno.to_string()).unwrap_or_default())
+                            }
+                            td class=(format!("diff-table__line diff-table__line--{}", diff_line_kind(line))) {
+                                (diff_line(line))
+                            }
+                        }
                     }
                 }
             }
@@ -444,50 +776,217 @@ pub fn refs(refs: (Vec<String>, Vec<String>), repo_url: String, _page: &Page) ->
     }
 }
 
-pub fn diff_file(file: &str) -> Markup {
-    let string = file.to_owned();
-    let diff = highlight::highlight("diff", &string);
+pub fn diff_file_split(file: &diff::FileDiff) -> Markup {
     html! {
-        pre.commit-diff {
-            (maud::PreEscaped(diff))
-        }
-    }
-}
-
-pub fn commit<'a>(commit: &git2::Commit, diff: Option<git2::Diff>) -> Markup {
-    let mut files: Vec<String> = vec![];
-    let mut current_diff: String = "".to_string();
-    let mut last_file_id: git2::Oid = git2::Oid::zero();
-    let mut num_lines: u16 = 0;
-    let mut too_big: bool = false;
-    // TODO be better
-    if let Some(diff) = &diff {
-        diff.print(git2::DiffFormat::Patch, |delta, _hunk, line| {
-            let new_file_id = delta.new_file().id();
-            if new_file_id != last_file_id {
-                files.push(current_diff.clone());
-                current_diff.clear();
-                last_file_id = new_file_id;
-            }
-            // TODO less expensive stuff here
-            current_diff = format!("{}{}", current_diff, line.origin().to_string());
-            current_diff = format!(
-                "{}{}",
-                current_diff,
-                std::str::from_utf8(line.content()).unwrap_or("")
-            );
-            // TODO something better than this
-            num_lines = num_lines + 1;
-            if num_lines > 12345 {
-                too_big = true;
-                false
-            } else {
-                true
-            }
-        })
-        .unwrap_or_default();
+        @if !file.hunks.is_empty() {
+            table.diff-table.diff-table--split {
+                @for hunk in &file.hunks {
+                    tr.diff-table__hunk {
+                        td colspan="4" {
+                            (hunk.header)
+                        }
+                    }
+                    @for (old, new) in hunk.rows() {
+                        tr {
+                            (split_side(old, true))
+                            (split_side(new, false))
+                        }
+                    }
+                }
+            }
+        }
     }
+}
+
+fn with_query(url: &str, key: &str, value: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}{}={}", url, separator, key, value)
+}
 
+// url is the page without the diff parameter
+pub fn diff_toggle(mode: diff::Mode, url: &str) -> Markup {
+    html! {
+        p.diff-toggle {
+            @for other in &[diff::Mode::Unified, diff::Mode::Split] {
+                @if *other == mode {
+                    span.diff-toggle__mode.diff-toggle__mode--current {
+                        (other.name())
+                    }
+                } @else if *other == diff::Mode::Unified {
+                    a.diff-toggle__mode href=(url) {
+                        (other.name())
+                    }
+                } @else {
+                    a.diff-toggle__mode href=(with_query(url, "diff", other.name())) {
+                        (other.name())
+                    }
+                }
+            }
+        }
+    }
+}
+
+// past this many lines the rest of the files are left out, which
+// diff::files knows not to bother making patches for
+const MAX_DIFF_LINES: usize = 12345;
+
+// command is what to run to see the whole thing when it's too big to show
+pub fn diff(
+    mut files: Vec<diff::FileDiff>,
+    command: &str,
+    mode: diff::Mode,
+    blob_url: BlobUrl,
+) -> Markup {
+    let shown = files.iter().take_while(|file| file.loaded).count();
+    let too_big = shown < files.len();
+    for file in &mut files[..shown] {
+        file.highlight();
+    }
+
+    html! {
+        @if too_big {
+            .commit-warning {
+                p {
+                    ("Hey, sorry. This diff is way too big so it has been truncated.")
+                }
+                p {
+                    ("Feel free to clone this repo and run ")
+
+                    code {
+                        ("`") (command) ("`")
+                    }
+
+                    ("to make sure you see everything")
+                }
+            }
+        }
+        @for (index, file) in files[..shown].iter().enumerate() {
+            section.diff-file id=(diff::FileDiff::anchor(index)) {
+                (diff_file_header(file))
+                (diff_file_binary(file, blob_url))
+                @match mode {
+                    diff::Mode::Unified => (diff_file(file)),
+                    diff::Mode::Split => (diff_file_split(file)),
+                }
+            }
+        }
+    }
+}
+
+// a line for each file with how much changed in it, like `git diff --stat`
+// but with links down to the files
+pub fn diffstat(files: &[diff::FileDiff]) -> Markup {
+    let most = files
+        .iter()
+        .map(|file| file.additions + file.deletions)
+        .max()
+        .unwrap_or(0);
+    let percent = |lines: usize| {
+        if most == 0 {
+            0.0
+        } else {
+            lines as f64 * 100.0 / most as f64
+        }
+    };
+    let additions: usize = files.iter().map(|file| file.additions).sum();
+    let deletions: usize = files.iter().map(|file| file.deletions).sum();
+    html! {
+        table.diffstat {
+            @for (index, file) in files.iter().enumerate() {
+                tr.diffstat-file {
+                    td.diffstat-file__name {
+                        a.diffstat-file__anchor href=(format!("#{}", diff::FileDiff::anchor(index))) {
+                            @if is_moved(file) {
+                                (file.old_path.as_ref().map(|p| &**p).unwrap_or(""))
+                                " → "
+                            }
+                            (file.path())
+                        }
+                    }
+                    td class=(format!("diffstat-file__status diffstat-file__status--{}", file.status_name().replace(' ', "-"))) {
+                        (file.status_name())
+                        @if let Some(similarity) = file.similarity {
+                            " (" (similarity) "%)"
+                        }
+                    }
+                    td.diffstat-file__additions {
+                        "+" (file.additions)
+                    }
+                    td.diffstat-file__deletions {
+                        "-" (file.deletions)
+                    }
+                    td.diffstat-file__graph {
+                        @if file.binary {
+                            "binary"
+                        }
+                        span.diffstat-bar.diffstat-bar--added
+                            style=(format!("width: {:.1}%", percent(file.additions))) {}
+                        span.diffstat-bar.diffstat-bar--deleted
+                            style=(format!("width: {:.1}%", percent(file.deletions))) {}
+                    }
+                }
+            }
+            tr.diffstat-total {
+                td colspan="2" {
+                    (files.len()) @if files.len() == 1 { " file" } @else { " files" } " changed"
+                }
+                td.diffstat-file__additions {
+                    "+" (additions)
+                }
+                td.diffstat-file__deletions {
+                    "-" (deletions)
+                }
+                td {}
+            }
+        }
+    }
+}
+
+// parent_number is which parent the diff is against, counting from 1
+pub fn commit<'a>(
+    commit: &git2::Commit,
+    diff: Option<git2::Diff>,
+    repo_url: &str,
+    parent_number: usize,
+    mode: diff::Mode,
+) -> Markup {
+    let is_merge = commit.parent_count() > 1;
+    let command = if is_merge {
+        format!("git diff {}^{} {}", commit.id(), parent_number, commit.id())
+    } else {
+        format!("git show {}", commit.id())
+    };
+    let commit_url = format!("{}/commit/{}", repo_url, commit.id());
+    let parent_url = |number: usize| {
+        if number == 1 {
+            commit_url.clone()
+        } else {
+            with_query(&commit_url, "parent", &number.to_string())
+        }
+    };
+    // `.patch` and `.diff` of the same diff as the page
+    let text_url = |extension: &str| {
+        let url = format!("{}{}", commit_url, extension);
+        if parent_number == 1 {
+            url
+        } else {
+            with_query(&url, "parent", &parent_number.to_string())
+        }
+    };
+    // the images come from the commit's own tree and the parent's
+    let blob_url = |side: &str, path: &str| {
+        let url = format!("{}/blobs/{}/{}/{}", repo_url, commit.id(), side, path);
+        if side == "old" && parent_number != 1 {
+            with_query(&url, "parent", &parent_number.to_string())
+        } else {
+            url
+        }
+    };
+    // the diff mode sticks when picking another parent
+    let mode_url = |url: String| match mode {
+        diff::Mode::Unified => url,
+        diff::Mode::Split => with_query(&url, "diff", mode.name()),
+    };
     html! {
         section.commit {
             pre.commit-message {
@@ -496,28 +995,85 @@ pub fn commit<'a>(commit: &git2::Commit, diff: Option<git2::Diff>) -> Markup {
             .commit-id {
                 (commit.id())
             }
-            @if too_big {
-                .commit-warning {
-                    p {
-                        ("Hey, sorry. This commit is way too big so the diff has been truncated.")
-                    }
-                    p {
-                        ("Feel free to clone this repo and run ")
-
-                        code {
-                            ("`git show ") (commit.id()) ("`")
+            p.commit-text {
+                a.commit-text__link href=(text_url(".patch")) {
+                    "patch"
+                }
+                a.commit-text__link href=(text_url(".diff")) {
+                    "diff"
+                }
+            }
+            @if commit.parent_count() > 0 {
+                p.commit-parents {
+                    @if is_merge { "parents " } @else { "parent " }
+                    @for (index, parent) in commit.parent_ids().enumerate() {
+                        @let short_id = parent.to_string()[..7].to_owned();
+                        a.commit-id.commit-parents__id href=(format!("{}/commit/{}", repo_url, parent)) {
+                            (short_id)
+                        }
+                        @if is_merge {
+                            @if index + 1 == parent_number {
+                                span.commit-parents__diff.commit-parents__diff--current {
+                                    "(diffed against)"
+                                }
+                            } @else {
+                                a.commit-parents__diff href=(mode_url(parent_url(index + 1))) {
+                                    "(diff against this)"
+                                }
+                            }
                         }
-
-                        ("to make sure you see everything")
                     }
                 }
             }
-            @if files.len() > 1 {
-                @for file in &files[1..] {
-                    (diff_file(&file))
+            @if let Some(diff) = diff {
+                @let files = diff::files(&diff, MAX_DIFF_LINES);
+                (diffstat(&files))
+                (diff_toggle(mode, &parent_url(parent_number)))
+                (self::diff(files, &command, mode, &blob_url))
+            }
+        }
+    }
+}
+
+// the diff is from merge_base to head_id, which is where its images come from
+pub fn compare(
+    base: &str,
+    head: &str,
+    log: Vec<git2::Commit>,
+    diff: &git2::Diff,
+    (merge_base, head_id): (git2::Oid, git2::Oid),
+    repo_url: String,
+    mode: diff::Mode,
+) -> Markup {
+    let compare_url = format!("{}/compare/{}...{}", repo_url, base, head);
+    // the merge base's own tree for the old side, not its parent's
+    let blob_url = |side: &str, path: &str| {
+        let commit = if side == "old" { merge_base } else { head_id };
+        format!("{}/blobs/{}/new/{}", repo_url, commit, path)
+    };
+    html! {
+        section.compare {
+            h2.compare-heading {
+                "comparing "
+                span.compare-heading__ref {
+                    (base)
+                }
+                "..."
+                span.compare-heading__ref {
+                    (head)
+                }
+            }
+            @if log.is_empty() {
+                p.compare-empty {
+                    (head) " has nothing that isn't in " (base) " already"
                 }
+            } @else {
+                (self::log(log, repo_url.clone(), &Page::Compare))
+                @let files = diff::files(diff, MAX_DIFF_LINES);
+                (diffstat(&files))
+                (diff_toggle(mode, &compare_url))
+                (self::diff(files, &format!("git diff {}...{}", base, head), mode, &blob_url))
             }
-            (diff_file(&current_diff))
         }
     }
 }
@@ -0,0 +1,143 @@
+use crate::diff;
+use crate::missing::Missing;
+use crate::repository::Repository;
+use chrono::prelude::*;
+use std::collections::BTreeMap;
+
+// patches as emails, the way `git format-patch` writes them, for piping into
+// `git am`
+
+fn git_error(_: git2::Error) -> Missing {
+    Missing::Nowhere
+}
+
+// the commit as one email: the From line, the headers, the message, a
+// diffstat and the diff. number and total are for the `[PATCH 2/5]` in a
+// series, and 1 and 1 for just `[PATCH]`
+pub fn patch<'repo>(
+    diff: &mut git2::Diff<'repo>,
+    commit: &git2::Commit<'repo>,
+    number: usize,
+    total: usize,
+) -> Result<String, Missing> {
+    let email = diff
+        .format_email(number, total, commit, None)
+        .map_err(git_error)?;
+    Ok(String::from_utf8_lossy(&email).into_owned())
+}
+
+// the From line's date is always this, so it's clear it isn't a real mbox
+// from line. the id says which commit it is, or all zeros for the cover
+// letter
+// https://git-scm.com/docs/git-format-patch#_discussion
+fn from_line(id: &str) -> String {
+    format!("From {} Mon Sep 17 00:00:00 2001\n", id)
+}
+
+// the dates in emails, in the commit's own timezone, or in UTC if its offset
+// is out of range
+fn date(time: git2::Time) -> String {
+    let offset = FixedOffset::east_opt(time.offset_minutes() * 60).unwrap_or_else(|| Utc.fix());
+    match offset.timestamp_opt(time.seconds(), 0).single() {
+        Some(date) => date.to_rfc2822(),
+        // a date from further away than chrono can count
+        _ => "Thu, 01 Jan 1970 00:00:00 +0000".to_owned(),
+    }
+}
+
+// who wrote what, like `git shortlog`
+fn shortlog(commits: &[git2::Commit]) -> String {
+    let mut authors: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for commit in commits {
+        let name = commit.author().name().unwrap_or("").to_owned();
+        let summary = commit.summary().unwrap_or("").to_owned();
+        authors.entry(name).or_default().push(summary);
+    }
+    let mut shortlog = String::new();
+    for (name, summaries) in authors {
+        shortlog += &format!("{} ({}):\n", name, summaries.len());
+        for summary in summaries {
+            shortlog += &format!("  {}\n", summary);
+        }
+        shortlog += "\n";
+    }
+    shortlog
+}
+
+// the first email, saying what's in the rest, from whoever wrote the head
+// commit. git leaves the subject and blurb for you to fill in, so they're
+// just the range and a shortlog here
+fn cover_letter(
+    repo: &Repository,
+    range: &str,
+    base: &git2::Commit,
+    head: &git2::Commit,
+    commits: &[git2::Commit],
+) -> Result<String, Missing> {
+    let merge_base = repo
+        .git2
+        .merge_base(base.id(), head.id())
+        .and_then(|merge_base| repo.git2.find_commit(merge_base))
+        .map_err(git_error)?;
+    let diff = repo
+        .git2
+        .diff_tree_to_tree(
+            merge_base.tree().ok().as_ref(),
+            head.tree().ok().as_ref(),
+            Some(&mut git2::DiffOptions::default()),
+        )
+        .and_then(diff::find_similar)
+        .map_err(git_error)?;
+    let diffstat = diff
+        .stats()
+        .and_then(|stats| {
+            stats.to_buf(
+                git2::DiffStatsFormat::FULL | git2::DiffStatsFormat::INCLUDE_SUMMARY,
+                72,
+            )
+        })
+        .map_err(git_error)?;
+
+    let author = head.author();
+    let mut letter = from_line(&git2::Oid::zero().to_string());
+    letter += &format!(
+        "From: {} <{}>\n",
+        author.name().unwrap_or(""),
+        author.email().unwrap_or("")
+    );
+    letter += &format!("Date: {}\n", date(author.when()));
+    letter += &format!("Subject: [PATCH 0/{}] {}\n\n", commits.len(), range);
+    letter += &shortlog(commits);
+    letter += &String::from_utf8_lossy(&diffstat);
+    letter += "\n-- \n\n";
+    Ok(letter)
+}
+
+// every commit in head that isn't in base as one mbox, oldest first and
+// numbered `[PATCH n/m]`, with a cover letter in front. merges are left out
+// like `git format-patch` leaves them out, since they don't make patches
+pub fn series(
+    repo: &Repository,
+    range: &str,
+    base_name: &str,
+    head_name: &str,
+) -> Result<String, Missing> {
+    let base = repo.find_commit(base_name)?;
+    let head = repo.find_commit(head_name)?;
+    let mut commits = repo.range_log(base.id(), head.id())?;
+    commits.retain(|commit| commit.parent_count() <= 1);
+    commits.reverse();
+    if commits.is_empty() {
+        return Err(Missing::Nowhere);
+    }
+
+    let mut mbox = cover_letter(repo, range, &base, &head, &commits)?;
+    for (index, commit) in commits.iter().enumerate() {
+        let mut diff = diff::commit_diff(&repo.git2, commit, 1, true).map_err(git_error)?;
+        let email = patch(&mut diff, commit, index + 1, commits.len())?;
+        // git2 leaves the numbers off when there's only one, but it's still
+        // one of one after the cover letter
+        mbox += &email.replacen("Subject: [PATCH] ", "Subject: [PATCH 1/1] ", 1);
+    }
+    Ok(mbox)
+}
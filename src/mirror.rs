@@ -0,0 +1,128 @@
+use crate::repository::Repository;
+use crate::user::{self, User};
+use chrono::prelude::*;
+use std::{cmp, thread, time};
+
+// `git config snootforge.mirror https://example.com/some/repo.git` in a bare
+// repo under the git root makes it a mirror of that
+static MIRROR_KEY: &str = "snootforge.mirror";
+// how often to fetch, in seconds
+static INTERVAL_KEY: &str = "snootforge.mirrorinterval";
+// when it last fetched without anything going wrong, in seconds since the
+// epoch. snootforge writes this one
+static SYNCED_KEY: &str = "snootforge.mirrorsynced";
+
+const DEFAULT_INTERVAL: i64 = 60 * 60;
+// the longest it waits before looking for new mirrors
+const MAX_SLEEP: i64 = 60;
+
+// everything, like `git clone --mirror`
+static REFSPEC: &str = "+refs/*:refs/*";
+
+pub struct Mirror {
+    pub url: String,
+    pub interval: i64,
+    pub synced: Option<DateTime<Utc>>,
+}
+
+impl Mirror {
+    // without any password that's in the url
+    pub fn display_url(&self) -> String {
+        let scheme_end = match self.url.find("://") {
+            Some(index) => index + 3,
+            None => return self.url.clone(),
+        };
+        let host_end = self.url[scheme_end..]
+            .find('/')
+            .map(|index| scheme_end + index)
+            .unwrap_or_else(|| self.url.len());
+        match self.url[scheme_end..host_end].rfind('@') {
+            Some(index) => format!(
+                "{}{}",
+                &self.url[..scheme_end],
+                &self.url[scheme_end + index + 1..]
+            ),
+            None => self.url.clone(),
+        }
+    }
+
+    fn due(&self, now: DateTime<Utc>) -> i64 {
+        match self.synced {
+            Some(synced) => synced.timestamp() + self.interval - now.timestamp(),
+            None => 0,
+        }
+    }
+}
+
+pub fn get(repo: &Repository) -> Option<Mirror> {
+    let config = repo.git2.config().ok()?;
+    let url = config.get_string(MIRROR_KEY).ok()?;
+    let interval = config.get_i64(INTERVAL_KEY).unwrap_or(DEFAULT_INTERVAL);
+    let synced = config
+        .get_i64(SYNCED_KEY)
+        .ok()
+        .map(|synced| Utc.timestamp(synced, 0));
+    Some(Mirror {
+        url,
+        interval: cmp::max(interval, 1),
+        synced,
+    })
+}
+
+// `git fetch --prune`, then point HEAD wherever upstream's points
+fn sync(repo: &Repository, mirror: &Mirror) -> Result<(), git2::Error> {
+    let mut remote = repo.git2.remote_anonymous(&mirror.url)?;
+    let mut options = git2::FetchOptions::new();
+    options.prune(git2::FetchPrune::On);
+    // the refspec gets all the tags already
+    options.download_tags(git2::AutotagOption::None);
+    remote.fetch(&[REFSPEC], Some(&mut options), Some("snootforge: mirror"))?;
+    if let Ok(head) = remote.default_branch() {
+        if let Some(head) = head.as_str() {
+            repo.git2.set_head(head)?;
+        }
+    }
+    let mut config = repo.git2.config()?;
+    config.set_i64(SYNCED_KEY, Utc::now().timestamp())
+}
+
+fn mirrors() -> Vec<(Repository, Mirror)> {
+    let mut mirrors = vec![];
+    for user_name in user::names() {
+        let user = match User::from_path(&super::get_git_root().join(&user_name)) {
+            Ok(user) => user,
+            Err(_) => continue,
+        };
+        for repo in user.repos {
+            if let Some(mirror) = get(&repo) {
+                mirrors.push((repo, mirror));
+            }
+        }
+    }
+    mirrors
+}
+
+// syncs every mirror that's due, and says how long until the next one is
+fn sync_due() -> i64 {
+    let mut next = MAX_SLEEP;
+    for (repo, mirror) in mirrors() {
+        let now = Utc::now();
+        if mirror.due(now) <= 0 {
+            eprintln!("mirroring {} into {}", mirror.display_url(), repo.url());
+            if let Err(error) = sync(&repo, &mirror) {
+                eprintln!("couldn't mirror {}: {}", repo.url(), error.message());
+            }
+            next = cmp::min(next, mirror.interval);
+        } else {
+            next = cmp::min(next, mirror.due(now));
+        }
+    }
+    next
+}
+
+pub fn schedule() {
+    thread::spawn(|| loop {
+        let next = cmp::max(sync_due(), 1);
+        thread::sleep(time::Duration::from_secs(next as u64));
+    });
+}
@@ -1,6 +1,21 @@
+use std::io;
+
 #[derive(Debug, PartialEq)]
 pub enum Missing {
     Nowhere,
     // Sometime, // this come back when there are new things to implement
     Elsewhere(String),
+    // needs to say who they are first
+    Unwelcome,
+    // a request that doesn't make sense, like a body that won't decode
+    Garbled,
+    // a request body bigger than we're willing to hold
+    TooBig,
+}
+
+// for when we're talking to a socket rather than making a page
+impl From<Missing> for io::Error {
+    fn from(missing: Missing) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, format!("missing {:?}", missing))
+    }
 }
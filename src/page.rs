@@ -1,8 +1,16 @@
+use crate::archive;
+use crate::blame;
+use crate::bundle;
+use crate::diff;
+use crate::highlight;
 use crate::markup;
+use crate::mbox;
 use crate::missing::Missing;
+use crate::receive_pack;
 use crate::repository::Repository;
-use crate::user::User;
-use crate::ContentType;
+use crate::upload_pack;
+use crate::user::{self, User};
+use crate::{ContentType, Download};
 
 use maud::html;
 use std::cmp::Ordering;
@@ -17,6 +25,7 @@ pub enum Page {
     Log,
     Blob,
     Commit,
+    Compare,
     Refs,
 }
 
@@ -136,6 +145,7 @@ pub fn tree(
     Ok(ContentType::Markup(
         html! {
             (markup::project_header(&repo, &Page::Tree))
+                (markup::tree_header(&tree, rest.map(|rest| rest.join("/")).as_ref().map(|p| &**p)))
                 (markup::tree(&tree, &Page::Tree))
                 @if let Ok(readme) = readme {
                     (markup::readme(readme, &Page::Tree))
@@ -149,24 +159,55 @@ pub fn project(name: &str, project_name: &str) -> Result<ContentType, Missing> {
     tree(name, project_name, None, None)
 }
 
-fn get_log<'a>(repo: &'a Repository, reff: Option<&str>) -> Result<Vec<git2::Commit<'a>>, Missing> {
-    let log = repo.log(reff);
-    match log {
-        Ok(log) => Ok(log),
-        Err(_) => Err(Missing::Nowhere),
-    }
-}
+// how many commits a page of the log gets, unless it asks for more, which
+// it can only do up to a point
+const LOG_LIMIT: usize = 50;
+const MAX_LOG_LIMIT: usize = 500;
 
 pub fn log(
     name: &str,
     project_name: &str,
     target: Option<&str>,
-    _rest: Option<&[&str]>,
+    rest: Option<&[&str]>,
+    after: Option<&str>,
+    limit: Option<&str>,
 ) -> Result<ContentType, Missing> {
     let repo = get_repo(name, project_name)?;
-    let log = get_log(&repo, target)?;
+    let after = match after.map(git2::Oid::from_str) {
+        Some(Ok(after)) => Some(after),
+        Some(Err(_)) => return Err(Missing::Nowhere),
+        None => None,
+    };
+    let limit = match limit.map(|limit| limit.parse::<usize>()) {
+        Some(Ok(limit)) if limit >= 1 => Some(limit.min(MAX_LOG_LIMIT)),
+        Some(_) => return Err(Missing::Nowhere),
+        None => None,
+    };
+    let file_path = rest.map(|rest| rest.join("/"));
+    let log = match &file_path {
+        Some(file_path) => repo.path_log(target, file_path, after, limit.unwrap_or(LOG_LIMIT))?,
+        None => repo.log_page(target, after, limit.unwrap_or(LOG_LIMIT))?,
+    };
+    // a path that's never been there. after the oldest commit is only the
+    // end of the log, and still gets its link back
+    if log.commits.is_empty() && after.is_none() {
+        return Err(Missing::Nowhere);
+    }
+    // the same page again, for the links to the pages either side
+    let mut log_url = format!("{}/log", repo.url());
+    if let Some(target) = target {
+        log_url = format!("{}/{}", log_url, target);
+    }
+    if let Some(file_path) = &file_path {
+        log_url = format!("{}/{}", log_url, file_path);
+    }
+    let title_prefix = match &file_path {
+        Some(file_path) => format!("{} - ", file_path),
+        None => "".to_string(),
+    };
     let title = format!(
-        "Log - {}/{} @ {}",
+        "{}Log - {}/{} @ {}",
+        title_prefix,
         name,
         project_name,
         target.unwrap_or(repo.head()?.name().unwrap_or(""))
@@ -174,7 +215,16 @@ pub fn log(
     Ok(ContentType::Markup(
         html! {
             (markup::project_header(&repo, &Page::Log))
-            (markup::log(log, repo.url(), &Page::Log))
+            @if let Some(file_path) = &file_path {
+                h2.log-path {
+                    "history of "
+                    span.log-path__path {
+                        (file_path)
+                    }
+                }
+            }
+            (markup::log(log.commits, repo.url(), &Page::Log))
+            (markup::log_pages(&log_url, log.previous, log.next, limit))
         },
         Some(title),
     ))
@@ -185,69 +235,59 @@ pub fn commit(
     project_name: &str,
     target: Option<&str>,
     _rest: Option<&[&str]>,
+    parent: Option<&str>,
+    diff_mode: Option<&str>,
 ) -> Result<ContentType, Missing> {
     let repo = get_repo(name, project_name)?;
-    let log = get_log(&repo, None)?;
     let target = match target {
         Some(t) => t,
         _ => return Err(Missing::Nowhere),
     };
-    let mut this_commit: Option<&git2::Commit> = None;
-    let mut prev_commit: Option<&git2::Commit> = None;
-
-    for (index, c) in log.iter().enumerate() {
-        if format!("{}", c.id()) == target {
-            this_commit = Some(c);
-            // this isn't really right at all
-            prev_commit = log.get(index + 1).map(|t| t);
-            break;
-        }
-    }
-
-    let commit_markup = match (this_commit, prev_commit) {
-        (Some(this), Some(prev)) => {
-            let this_tree = this.tree().map(|t| Some(t)).unwrap_or(None);
-            let that_tree = prev.tree().map(|t| Some(t)).unwrap_or(None);
-            let diff = match repo.git2.diff_tree_to_tree(
-                that_tree.as_ref(),
-                this_tree.as_ref(),
-                Some(&mut git2::DiffOptions::default()),
-            ) {
-                Ok(diff) => Some(diff),
-                _ => None,
-            };
-            markup::commit(this, diff)
+    // `<id>.patch` is the commit as an email for `git am`, and `<id>.diff`
+    // just the diff
+    let (target, extension) = match target.rfind('.') {
+        Some(index) if [".patch", ".diff"].contains(&&target[index..]) => {
+            (&target[..index], Some(&target[index..]))
         }
-        (Some(this), None) => {
-            let this_tree = this.tree().map(|t| Some(t)).unwrap_or(None);
-            let diff = match repo.git2.diff_tree_to_tree(
-                None,
-                this_tree.as_ref(),
-                Some(&mut git2::DiffOptions::default()),
-            ) {
-                Ok(diff) => Some(diff),
-                _ => None,
-            };
-            markup::commit(this, diff)
-        }
-        _ => return Err(Missing::Nowhere),
+        _ => (target, None),
     };
-
-    let title = match this_commit {
-        Some(commit) => format!(
-            "{} - {}/{}@{}",
-            commit.summary().unwrap_or("Commit"),
-            name,
-            project_name,
-            target
-        ),
-        None => format!("Commit - {}/{}@{}", name, project_name, target),
+    let commit = repo.find_commit(target)?;
+    // merges get diffed against one parent at a time, counting from 1 like
+    // `commit^2` does, and the first one unless it says otherwise
+    let parent_number = match parent {
+        Some(parent) => match parent.parse::<usize>() {
+            Ok(number) if number >= 1 && number <= commit.parent_count() => number,
+            _ => return Err(Missing::Nowhere),
+        },
+        None => 1,
     };
+    let diff = diff::commit_diff(&repo.git2, &commit, parent_number, extension.is_some()).ok();
+
+    if let Some(extension) = extension {
+        let mut diff = match diff {
+            Some(diff) => diff,
+            None => return Err(Missing::Nowhere),
+        };
+        let text = if extension == ".patch" {
+            mbox::patch(&mut diff, &commit, 1, 1)?
+        } else {
+            diff::text(&diff)
+        };
+        return Ok(ContentType::PlainText(text));
+    }
+
+    let title = format!(
+        "{} - {}/{}@{}",
+        commit.summary().unwrap_or("Commit"),
+        name,
+        project_name,
+        target
+    );
 
     Ok(ContentType::Markup(
         html! {
             (markup::project_header(&repo, &Page::Commit))
-            (commit_markup)
+            (markup::commit(&commit, diff, &repo.url(), parent_number, diff::Mode::from_query(diff_mode)))
         },
         Some(title),
     ))
@@ -285,6 +325,18 @@ pub fn blob(
         rest.unwrap_or(&[]).join("/")
     );
     let raw_redirect = Err(Missing::Elsewhere(raw_url.to_string()));
+    let blame_url = format!(
+        "{}/blame/{}/{}",
+        repo.url(),
+        target.unwrap_or(""),
+        rest.unwrap_or(&[]).join("/")
+    );
+    let log_url = format!(
+        "{}/log/{}/{}",
+        repo.url(),
+        target.unwrap_or(""),
+        rest.unwrap_or(&[]).join("/")
+    );
     let blob = match get_blob(&repo, target, rest) {
         Ok(blob) => match std::str::from_utf8(&blob) {
             Ok(blob) => blob.to_owned(),
@@ -312,7 +364,7 @@ pub fn blob(
         html! {
             (markup::project_header(&repo, &Page::Blob))
                 article.blob {
-                    (markup::blob_header(directory.unwrap(), &raw_url, &directory_url.unwrap(), file_name.unwrap()))
+                    (markup::blob_header(directory.unwrap(), &raw_url, &directory_url.unwrap(), file_name.unwrap(), &[("Blame", &blame_url), ("History", &log_url)]))
                     (markup::blob(file_extension.unwrap(), blob, &Page::Blob))
                 }
         },
@@ -320,6 +372,145 @@ pub fn blob(
     ))
 }
 
+pub fn compare(
+    name: &str,
+    project_name: &str,
+    target: Option<&str>,
+    rest: Option<&[&str]>,
+    diff_mode: Option<&str>,
+) -> Result<ContentType, Missing> {
+    let repo = get_repo(name, project_name)?;
+    let mut range = match target {
+        Some(target) => target.to_owned(),
+        None => return Err(Missing::Nowhere),
+    };
+    for part in rest.unwrap_or(&[]) {
+        range += "/";
+        range += part;
+    }
+    // `main...feature`, which is what github does too
+    let mut parts = range.splitn(2, "...");
+    let (base_name, head_name) = match (parts.next(), parts.next()) {
+        (Some(base), Some(head)) if !base.is_empty() && !head.is_empty() => (base, head),
+        _ => return Err(Missing::Nowhere),
+    };
+    let base = repo.find_commit(base_name)?;
+    let head = repo.find_commit(head_name)?;
+    let merge_base = match repo
+        .git2
+        .merge_base(base.id(), head.id())
+        .and_then(|merge_base| repo.git2.find_commit(merge_base))
+    {
+        Ok(merge_base) => merge_base,
+        Err(_) => return Err(Missing::Nowhere),
+    };
+    let log = repo.range_log(base.id(), head.id())?;
+    let diff = match repo
+        .git2
+        .diff_tree_to_tree(
+            merge_base.tree().ok().as_ref(),
+            head.tree().ok().as_ref(),
+            Some(&mut git2::DiffOptions::default()),
+        )
+        .and_then(diff::find_similar)
+    {
+        Ok(diff) => diff,
+        Err(_) => return Err(Missing::Nowhere),
+    };
+    let title = format!("{}...{} - {}/{}", base_name, head_name, name, project_name);
+    Ok(ContentType::Markup(
+        html! {
+            (markup::project_header(&repo, &Page::Compare))
+            (markup::compare(base_name, head_name, log, &diff, (merge_base.id(), head.id()), repo.url(), diff::Mode::from_query(diff_mode)))
+        },
+        Some(title),
+    ))
+}
+
+// `mbox/main..feature`, for `git am`
+pub fn mbox(
+    name: &str,
+    project_name: &str,
+    target: Option<&str>,
+    rest: Option<&[&str]>,
+) -> Result<ContentType, Missing> {
+    let repo = get_repo(name, project_name)?;
+    let mut range = match target {
+        Some(target) => target.to_owned(),
+        None => return Err(Missing::Nowhere),
+    };
+    for part in rest.unwrap_or(&[]) {
+        range += "/";
+        range += part;
+    }
+    let mut parts = range.splitn(2, "..");
+    let (base_name, head_name) = match (parts.next(), parts.next()) {
+        // `main...feature` is for compare
+        (Some(base), Some(head))
+            if !base.is_empty() && !head.is_empty() && !head.starts_with('.') =>
+        {
+            (base, head)
+        }
+        _ => return Err(Missing::Nowhere),
+    };
+    Ok(ContentType::PlainText(mbox::series(
+        &repo, &range, base_name, head_name,
+    )?))
+}
+
+pub fn blame(
+    name: &str,
+    project_name: &str,
+    target: Option<&str>,
+    rest: Option<&[&str]>,
+) -> Result<ContentType, Missing> {
+    let repo = get_repo(name, project_name)?;
+    let refname = repo.get_refname(target)?;
+    let file_path = match rest {
+        Some(rest) => rest.join("/"),
+        None => return Err(Missing::Nowhere),
+    };
+    let raw_url = format!("{}/raw/{}/{}", repo.url(), refname, file_path);
+    let blob_url = format!("{}/blob/{}/{}", repo.url(), refname, file_path);
+    let log_url = format!("{}/log/{}/{}", repo.url(), refname, file_path);
+    let blob = match get_blob(&repo, target, rest) {
+        Ok(blob) => match std::str::from_utf8(&blob) {
+            Ok(blob) => blob.to_owned(),
+            _ => return Err(Missing::Elsewhere(raw_url)),
+        },
+        _ => return Err(Missing::Nowhere),
+    };
+    let hunks = blame::blame(&repo, &refname, &file_path)?;
+
+    let subpath = path::PathBuf::from(&file_path);
+    let file_extension = subpath
+        .extension()
+        .unwrap_or(subpath.file_name().unwrap_or_default())
+        .to_str()
+        .unwrap_or("");
+    let directory = subpath
+        .parent()
+        .and_then(|directory| directory.to_str())
+        .unwrap_or("");
+    let directory_url = format!("{}/tree/{}/{}", repo.url(), refname, directory);
+    let file_name = rest.unwrap_or(&[]).last().unwrap_or(&"");
+    let lines = highlight::highlight_lines(file_extension, &blob);
+    let title = format!(
+        "blame {} ({}) - {}/{}@{}",
+        file_name, directory, name, project_name, refname
+    );
+    Ok(ContentType::Markup(
+        html! {
+            (markup::project_header(&repo, &Page::Blob))
+                article.blob {
+                    (markup::blob_header(directory, &raw_url, &directory_url, file_name, &[("Blob", &blob_url), ("History", &log_url)]))
+                    (markup::blame(&hunks, &lines, &repo.url()))
+                }
+        },
+        Some(title),
+    ))
+}
+
 pub fn refs(
     name: &str,
     project_name: &str,
@@ -358,6 +549,139 @@ pub fn raw(
     Ok(ContentType::Binary(mime, blob))
 }
 
+// the files on either side of a diff, for its images. `blobs/<commit>/new/<path>`
+// is the file in the commit and `blobs/<commit>/old/<path>` the one in the
+// parent it was diffed against, so only blobs that are really in its history
+// get out, not anything that happens to be lying around in the odb
+pub fn diff_blob(
+    name: &str,
+    project_name: &str,
+    target: Option<&str>,
+    rest: Option<&[&str]>,
+    parent: Option<&str>,
+) -> Result<ContentType, Missing> {
+    let repo = get_repo(name, project_name)?;
+    let commit = match target {
+        Some(target) => repo.find_commit(target)?,
+        None => return Err(Missing::Nowhere),
+    };
+    let (side, path) = match rest {
+        Some(rest) if rest.len() > 1 => (rest[0], &rest[1..]),
+        _ => return Err(Missing::Nowhere),
+    };
+    let commit = match side {
+        "new" => commit,
+        "old" => {
+            let parent_number = match parent.map(|parent| parent.parse::<usize>()) {
+                Some(Ok(number)) if number >= 1 => number,
+                Some(_) => return Err(Missing::Nowhere),
+                None => 1,
+            };
+            match commit.parent(parent_number - 1) {
+                Ok(parent) => parent,
+                Err(_) => return Err(Missing::Nowhere),
+            }
+        }
+        _ => return Err(Missing::Nowhere),
+    };
+    let blob = match commit
+        .tree()
+        .and_then(|tree| tree.get_path(&path::PathBuf::from(path.join("/"))))
+        .and_then(|entry| entry.to_object(&repo.git2))
+        .map(|object| object.into_blob())
+    {
+        Ok(Ok(blob)) => blob,
+        _ => return Err(Missing::Nowhere),
+    };
+    let file = path.last().unwrap_or(&"");
+    let mime = format!("{}", super::guess_mime(file));
+    Ok(ContentType::Binary(mime, blob.content().to_vec()))
+}
+
+// `archive/main.tar.gz`, or `archive/main.zip.sha256` for its checksum, and
+// `?path=docs` for only that directory. refs with slashes in them end up
+// spread over target and rest
+pub fn archive(
+    name: &str,
+    project_name: &str,
+    target: Option<&str>,
+    rest: Option<&[&str]>,
+    directory: Option<&str>,
+    range: Option<&str>,
+    if_range: Option<&str>,
+) -> Result<ContentType, Missing> {
+    let repo = get_repo(name, project_name)?;
+    let mut filename = match target {
+        Some(target) => target.to_owned(),
+        None => return Err(Missing::Nowhere),
+    };
+    for part in rest.unwrap_or(&[]) {
+        filename += "/";
+        filename += part;
+    }
+    // `v1.tar.gz.sha256` is the checksum of v1.tar.gz
+    let checksum = filename.ends_with(".sha256");
+    if checksum {
+        filename.truncate(filename.len() - ".sha256".len());
+    }
+    let (refname, format) = match archive::Format::split(&filename) {
+        Some(split) => split,
+        None => return Err(Missing::Nowhere),
+    };
+    let (etag, archive) = archive::archive(&repo, refname, directory, format)?;
+    if checksum {
+        // named the way `curl -O` would save it
+        let saved_as = filename.rsplit('/').next().unwrap_or("");
+        return Ok(ContentType::PlainText(archive::checksum(
+            &archive, saved_as,
+        )?));
+    }
+    Ok(ContentType::Resumable(
+        format.content_type().to_string(),
+        Download::File(archive),
+        etag.clone(),
+        resumed_range(range, if_range, &etag),
+    ))
+}
+
+// `bundle/main`, or `bundle/v1..main` for only what's new since v1. refs
+// with slashes in them end up spread over target and rest
+pub fn bundle(
+    name: &str,
+    project_name: &str,
+    target: Option<&str>,
+    rest: Option<&[&str]>,
+    range: Option<&str>,
+    if_range: Option<&str>,
+) -> Result<ContentType, Missing> {
+    let repo = get_repo(name, project_name)?;
+    let mut refs = match target {
+        Some(target) => target.to_owned(),
+        None => return Err(Missing::Nowhere),
+    };
+    for part in rest.unwrap_or(&[]) {
+        refs += "/";
+        refs += part;
+    }
+    let (etag, bundle) = bundle::bundle(&repo, &refs)?;
+    Ok(ContentType::Resumable(
+        bundle::CONTENT_TYPE.to_string(),
+        Download::Bytes(bundle),
+        etag.clone(),
+        resumed_range(range, if_range, &etag),
+    ))
+}
+
+// a ref can move between a download starting and being resumed, so the range
+// only counts if If-Range says the client has the start of this version.
+// otherwise it gets all of this one
+fn resumed_range(range: Option<&str>, if_range: Option<&str>, etag: &str) -> Option<String> {
+    match if_range {
+        Some(if_range) if if_range != etag => None,
+        _ => range.map(|range| range.to_owned()),
+    }
+}
+
 // TODO the below should not be part of page
 
 pub fn head(
@@ -367,11 +691,15 @@ pub fn head(
     _rest: Option<&[&str]>,
 ) -> Result<ContentType, Missing> {
     let repo = get_repo(name, project_name)?;
-    let head: git2::Reference = repo.head()?;
-    if let Some(head) = head.name() {
-        Ok(ContentType::PlainText(format!("ref: {}{}", head, "\n\n")))
-    } else {
-        Err(Missing::Nowhere)
+    // not repo.head(), because that has already followed the symref
+    let head = match repo.git2.find_reference("HEAD") {
+        Ok(head) => head,
+        Err(_) => return Err(Missing::Nowhere),
+    };
+    match (head.symbolic_target(), head.target()) {
+        (Some(target), _) => Ok(ContentType::PlainText(format!("ref: {}\n", target))),
+        (None, Some(id)) => Ok(ContentType::PlainText(format!("{}\n", id))),
+        _ => Err(Missing::Nowhere),
     }
 }
 
@@ -380,26 +708,44 @@ pub fn info(
     project_name: &str,
     target: Option<&str>,
     _rest: Option<&[&str]>,
+    service: Option<&str>,
+    git_protocol: Option<&str>,
+    authorization: Option<&str>,
 ) -> Result<ContentType, Missing> {
     let target = target.unwrap_or("");
     if target != "refs" {
         return Err(Missing::Nowhere);
     }
     let repo = get_repo(name, project_name)?;
-    let references = repo.references()?;
-    let mut lines = vec![];
-    for reference in references {
-        if let Ok(reference) = reference {
-            let id = reference.target().unwrap_or(git2::Oid::zero());
-
-            lines.push(format!("{}\t{}", id, reference.name().unwrap_or("")))
+    if service == Some(upload_pack::SERVICE) {
+        return Ok(ContentType::Binary(
+            upload_pack::ADVERTISEMENT_TYPE.to_string(),
+            upload_pack::info_refs(&repo, upload_pack::protocol_version(git_protocol))?,
+        ));
+    }
+    if service == Some(receive_pack::SERVICE) {
+        user::authorize(name, authorization)?;
+        return Ok(ContentType::Binary(
+            receive_pack::ADVERTISEMENT_TYPE.to_string(),
+            receive_pack::info_refs(&repo)?,
+        ));
+    }
+    // the same as `git update-server-info` would write
+    let mut refs = String::new();
+    for tip in repo.tips()? {
+        refs += &format!("{}\t{}\n", tip.id, tip.name);
+        if let Some(peeled) = tip.peeled {
+            refs += &format!("{}\t{}^{{}}\n", peeled, tip.name);
         }
     }
-    Ok(ContentType::PlainText(format!(
-        "{}{}",
-        lines.join("\n"),
-        "\n"
-    )))
+    Ok(ContentType::PlainText(refs))
+}
+
+fn get_objects_path(name: &str, project_name: &str) -> path::PathBuf {
+    let mut objects_path = get_user_root(name);
+    objects_path.push(format!("{}.git", project_name));
+    objects_path.push("objects");
+    objects_path
 }
 
 pub fn pack_info(
@@ -408,45 +754,144 @@ pub fn pack_info(
     _target: Option<&str>,
     _rest: Option<&[&str]>,
 ) -> Result<ContentType, Missing> {
-    let mut packs_path = get_user_root(name);
-    packs_path.push(format!("{}.git", project_name));
-    packs_path.push("objects");
+    let mut packs_path = get_objects_path(name, project_name);
     packs_path.push("pack");
 
-    let packs = fs::read_dir(packs_path).expect("i couldn't find the objects/pack directory :(");
     let mut pack_list = String::new();
-    for pack in packs {
-        let pack: fs::DirEntry = pack.unwrap();
-        let name = pack.file_name();
-        let name = name.to_str().unwrap_or_default();
-        if name.ends_with(".pack") {
+    // a repo with only loose objects doesn't have to have a pack directory
+    if let Ok(packs) = fs::read_dir(packs_path) {
+        let mut names: Vec<String> = packs
+            .filter_map(|pack| pack.ok())
+            .filter_map(|pack| pack.file_name().into_string().ok())
+            .filter(|name| name.ends_with(".pack"))
+            .collect();
+        names.sort();
+        for name in names {
             pack_list += &format!("P {}\n", name);
         }
     }
     Ok(ContentType::PlainText(format!("{}\n", pack_list)))
 }
 
+// objects/info/alternates is full of paths on this computer, so turn the ones
+// inside the git root into urls on this server
+pub fn alternates(
+    name: &str,
+    project_name: &str,
+    _target: Option<&str>,
+    _rest: Option<&[&str]>,
+) -> Result<ContentType, Missing> {
+    let objects_path = get_objects_path(name, project_name);
+    let alternates = match fs::read_to_string(objects_path.join("info").join("alternates")) {
+        Ok(alternates) => alternates,
+        Err(_) => return Err(Missing::Nowhere),
+    };
+    let git_root = match super::get_git_root().canonicalize() {
+        Ok(git_root) => git_root,
+        Err(_) => return Err(Missing::Nowhere),
+    };
+    let mut urls = String::new();
+    for alternate in alternates.lines() {
+        if alternate.is_empty() || alternate.starts_with('#') {
+            continue;
+        }
+        let alternate = match objects_path.join(alternate).canonicalize() {
+            Ok(alternate) => alternate,
+            Err(_) => continue,
+        };
+        let alternate = match alternate.strip_prefix(&git_root) {
+            Ok(alternate) => alternate,
+            Err(_) => continue,
+        };
+        let parts: Vec<&str> = alternate
+            .components()
+            .filter_map(|component| component.as_os_str().to_str())
+            .collect();
+        if let [user_name, repo_name, "objects"] = parts[..] {
+            if repo_name.ends_with(".git") {
+                urls += &format!(
+                    "/{}/{}/objects\n",
+                    user_name,
+                    &repo_name[..repo_name.len() - 4]
+                );
+            }
+        }
+    }
+    Ok(ContentType::PlainText(urls))
+}
+
+fn is_object_path(folder: &str, file: &str) -> bool {
+    let is_hex = |string: &str| string.chars().all(|c| c.is_ascii_hexdigit());
+    match folder {
+        "pack" => {
+            file.starts_with("pack-")
+                && (file.ends_with(".pack") || file.ends_with(".idx"))
+                && !file.contains("..")
+        }
+        _ => folder.len() == 2 && is_hex(folder) && file.len() == 38 && is_hex(file),
+    }
+}
+
 pub fn objects(
     name: &str,
     project_name: &str,
     target: Option<&str>,
     rest: Option<&[&str]>,
 ) -> Result<ContentType, Missing> {
-    let user_root = get_user_root(name);
-    let mut object_path = std::path::PathBuf::from(user_root);
-    let folder = target.unwrap();
-    let file = rest.unwrap_or(&[""])[0];
-    if folder == "info" && file == "packs" {
-        return pack_info(name, project_name, target, rest);
+    let (folder, file) = match (target, rest) {
+        (Some(folder), Some([file])) => (folder, *file),
+        _ => return Err(Missing::Nowhere),
+    };
+    match (folder, file) {
+        ("info", "packs") => return pack_info(name, project_name, target, rest),
+        ("info", "alternates") | ("info", "http-alternates") => {
+            return alternates(name, project_name, target, rest)
+        }
+        _ => {}
+    }
+    if !is_object_path(folder, file) {
+        return Err(Missing::Nowhere);
     }
-    object_path.push(format!("{}.git", project_name));
-    object_path.push("objects");
+    let mut object_path = get_objects_path(name, project_name);
     object_path.push(folder);
     object_path.push(file);
 
+    let content_type = match (folder, file.ends_with(".idx")) {
+        ("pack", true) => "application/x-git-packed-objects-toc",
+        ("pack", false) => "application/x-git-packed-objects",
+        _ => "application/x-git-loose-object",
+    };
     if let Ok(content) = std::fs::read(&object_path) {
-        return Ok(ContentType::Binary("application/zlib".to_string(), content));
+        return Ok(ContentType::Binary(content_type.to_string(), content));
     }
 
     Err(Missing::Nowhere)
 }
+
+pub fn upload_pack(
+    name: &str,
+    project_name: &str,
+    body: Vec<u8>,
+    git_protocol: Option<&str>,
+) -> Result<ContentType, Missing> {
+    let repo = get_repo(name, project_name)?;
+    let version = upload_pack::protocol_version(git_protocol);
+    Ok(ContentType::Streamed(
+        upload_pack::RESULT_TYPE.to_string(),
+        Box::new(move |output| upload_pack::upload_pack(&repo, &body, version, output)),
+    ))
+}
+
+pub fn receive_pack(
+    name: &str,
+    project_name: &str,
+    body: &[u8],
+    authorization: Option<&str>,
+) -> Result<ContentType, Missing> {
+    user::authorize(name, authorization)?;
+    let repo = get_repo(name, project_name)?;
+    Ok(ContentType::Binary(
+        receive_pack::RESULT_TYPE.to_string(),
+        receive_pack::receive_pack(&repo, body)?,
+    ))
+}
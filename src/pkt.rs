@@ -0,0 +1,167 @@
+use crate::missing::Missing;
+use std::io::{self, Read, Write};
+use std::str;
+
+// https://git-scm.com/docs/protocol-common#_pkt_line_format
+pub const FLUSH: &[u8] = b"0000";
+pub const DELIM: &[u8] = b"0001";
+
+// the biggest pkt-line is 65520 bytes (or 1000 with plain side-band), minus 4
+// for the length and 1 for the sideband channel
+pub const SIDEBAND_64K: usize = 65515;
+pub const SIDEBAND: usize = 995;
+
+#[derive(Debug, PartialEq)]
+pub enum Packet<'a> {
+    Flush,
+    Delim,
+    Data(&'a [u8]),
+}
+
+impl<'a> Packet<'a> {
+    // the line without its trailing newline, if it's a text line
+    pub fn text(&self) -> Option<&'a str> {
+        match self {
+            Packet::Data(data) => match str::from_utf8(data) {
+                Ok(line) => Some(line.trim_end_matches('\n')),
+                Err(_) => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut packet = format!("{:04x}", data.len() + 4).into_bytes();
+    packet.extend_from_slice(data);
+    packet
+}
+
+pub fn line(line: &str) -> Vec<u8> {
+    encode(line.as_bytes())
+}
+
+// the capabilities ride along on the first ref, or on a fake one if there are
+// no refs yet
+pub fn advertisement(refs: &[(git2::Oid, String)], capabilities: &str) -> Vec<u8> {
+    let mut output = vec![];
+    if refs.is_empty() {
+        output.append(&mut line(&format!(
+            "{} capabilities^{{}}\0{}\n",
+            git2::Oid::zero(),
+            capabilities
+        )));
+    }
+    for (index, (id, name)) in refs.iter().enumerate() {
+        let ref_line = if index == 0 {
+            format!("{} {}\0{}\n", id, name, capabilities)
+        } else {
+            format!("{} {}\n", id, name)
+        };
+        output.append(&mut line(&ref_line));
+    }
+    output.extend_from_slice(FLUSH);
+    output
+}
+
+pub fn sideband(band: u8, data: &[u8], size: usize) -> Vec<u8> {
+    let mut output = vec![];
+    for chunk in data.chunks(size) {
+        let mut packet = Vec::with_capacity(chunk.len() + 1);
+        packet.push(band);
+        packet.extend_from_slice(chunk);
+        output.append(&mut encode(&packet));
+    }
+    output
+}
+
+// puts everything written to it in sideband packets on the one band, so a
+// pack can go out as it's made
+pub struct Sideband<'a> {
+    pub output: &'a mut dyn Write,
+    pub band: u8,
+    pub size: usize,
+}
+
+impl Write for Sideband<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.output
+            .write_all(&sideband(self.band, data, self.size))?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+// reads one packet off the front of the input, and returns it with the rest
+pub fn read(input: &[u8]) -> Result<(Packet, &[u8]), Missing> {
+    if input.len() < 4 {
+        return Err(Missing::Nowhere);
+    }
+    let length = match str::from_utf8(&input[..4]) {
+        Ok(length) => match usize::from_str_radix(length, 16) {
+            Ok(length) => length,
+            Err(_) => return Err(Missing::Nowhere),
+        },
+        Err(_) => return Err(Missing::Nowhere),
+    };
+    match length {
+        0 => Ok((Packet::Flush, &input[4..])),
+        1 => Ok((Packet::Delim, &input[4..])),
+        2 | 3 => Err(Missing::Nowhere),
+        _ if length > input.len() => Err(Missing::Nowhere),
+        _ => Ok((Packet::Data(&input[4..length]), &input[length..])),
+    }
+}
+
+pub fn decode(mut input: &[u8]) -> Result<Vec<Packet>, Missing> {
+    let mut packets = vec![];
+    while !input.is_empty() {
+        let (packet, rest) = read(input)?;
+        packets.push(packet);
+        input = rest;
+    }
+    Ok(packets)
+}
+
+// one whole packet, length and all, off a connection
+pub fn read_raw<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut packet = vec![0; 4];
+    reader.read_exact(&mut packet)?;
+    let length = match str::from_utf8(&packet) {
+        Ok(length) => usize::from_str_radix(length, 16).ok(),
+        Err(_) => None,
+    };
+    match length {
+        Some(0) | Some(1) => Ok(packet),
+        Some(length) if length >= 4 => {
+            packet.resize(length, 0);
+            reader.read_exact(&mut packet[4..])?;
+            Ok(packet)
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "bad pkt-line")),
+    }
+}
+
+// everything up to and including the next flush, or nothing if they hung up
+pub fn read_request<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut request = vec![];
+    loop {
+        let mut packet = match read_raw(reader) {
+            Ok(packet) => packet,
+            Err(ref error)
+                if error.kind() == io::ErrorKind::UnexpectedEof && request.is_empty() =>
+            {
+                return Ok(None)
+            }
+            Err(error) => return Err(error),
+        };
+        let flush = packet == FLUSH;
+        request.append(&mut packet);
+        if flush {
+            return Ok(Some(request));
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use crate::mirror;
+use crate::missing::Missing;
+use crate::pkt::{self, Packet};
+use crate::repository::Repository;
+use crate::upload_pack;
+use std::io::{self, Read, Write};
+
+// https://git-scm.com/docs/pack-protocol#_pushing_data_to_a_server
+pub const SERVICE: &str = "git-receive-pack";
+pub const ADVERTISEMENT_TYPE: &str = "application/x-git-receive-pack-advertisement";
+pub const RESULT_TYPE: &str = "application/x-git-receive-pack-result";
+
+const CAPABILITIES: &str = "report-status delete-refs ofs-delta";
+
+// set this in a repo's config to let people push rewritten history
+const ALLOW_NON_FAST_FORWARD: &str = "snootforge.allownonfastforward";
+
+pub fn advertisement(repo: &Repository) -> Result<Vec<u8>, Missing> {
+    let lines: Vec<(git2::Oid, String)> = repo
+        .tips()?
+        .into_iter()
+        .map(|tip| (tip.id, tip.name))
+        .collect();
+    let capabilities = format!("{} {}", CAPABILITIES, upload_pack::agent());
+    Ok(pkt::advertisement(&lines, &capabilities))
+}
+
+pub fn info_refs(repo: &Repository) -> Result<Vec<u8>, Missing> {
+    let mut output = pkt::line(&format!("# service={}\n", SERVICE));
+    output.extend_from_slice(pkt::FLUSH);
+    output.append(&mut advertisement(repo)?);
+    Ok(output)
+}
+
+struct Command {
+    old: git2::Oid,
+    new: git2::Oid,
+    name: String,
+}
+
+fn parse_id(id: &str) -> Result<git2::Oid, Missing> {
+    match git2::Oid::from_str(id) {
+        Ok(id) => Ok(id),
+        Err(_) => Err(Missing::Nowhere),
+    }
+}
+
+// the commands come first, then a flush, then the pack (unless it's all
+// deletes)
+fn commands(request: &[u8]) -> Result<(Vec<Command>, &[u8]), Missing> {
+    let mut commands = vec![];
+    let mut rest = request;
+    while !rest.is_empty() {
+        let (packet, remaining) = pkt::read(rest)?;
+        rest = remaining;
+        let line = match packet {
+            Packet::Flush => break,
+            Packet::Delim => return Err(Missing::Nowhere),
+            _ => match packet.text() {
+                Some(line) => line,
+                None => return Err(Missing::Nowhere),
+            },
+        };
+        // the first command has the capabilities hanging off a NUL
+        let line = line.split('\0').next().unwrap_or("");
+        if line.starts_with("shallow ") {
+            continue;
+        }
+        let mut words = line.splitn(3, ' ');
+        match (words.next(), words.next(), words.next()) {
+            (Some(old), Some(new), Some(name)) => commands.push(Command {
+                old: parse_id(old)?,
+                new: parse_id(new)?,
+                name: name.to_owned(),
+            }),
+            _ => return Err(Missing::Nowhere),
+        }
+    }
+    Ok((commands, rest))
+}
+
+fn unpack(repo: &Repository, pack: &[u8]) -> Result<(), git2::Error> {
+    if pack.is_empty() {
+        return Ok(());
+    }
+    let odb = repo.git2.odb()?;
+    let mut writer = odb.packwriter()?;
+    if writer.write_all(pack).is_err() {
+        return Err(git2::Error::from_str("couldn't write the pack"));
+    }
+    writer.commit()?;
+    Ok(())
+}
+
+fn allows_non_fast_forward(repo: &Repository) -> bool {
+    match repo.git2.config() {
+        Ok(config) => config.get_bool(ALLOW_NON_FAST_FORWARD).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+// a ref someone else moved since the client looked at it
+fn stale(error: git2::Error) -> &'static str {
+    match error.code() {
+        git2::ErrorCode::Modified | git2::ErrorCode::Exists | git2::ErrorCode::NotFound => {
+            "stale info"
+        }
+        _ => "failed to update ref",
+    }
+}
+
+// deleting under the ref's lock, so it can't move between the check and the
+// delete
+fn delete(repo: &Repository, command: &Command) -> Result<(), &'static str> {
+    let mut transaction = repo.git2.transaction().map_err(|_| "failed to delete")?;
+    transaction
+        .lock_ref(&command.name)
+        .map_err(|_| "failed to lock")?;
+    match repo.git2.refname_to_id(&command.name) {
+        Ok(current) if current == command.old => {}
+        _ => return Err("stale info"),
+    }
+    transaction
+        .remove(&command.name)
+        .and_then(|_| transaction.commit())
+        .map_err(|_| "failed to delete")
+}
+
+// the error is the reason that goes back to the client on the `ng` line
+fn update(repo: &Repository, command: &Command, force: bool) -> Result<(), &'static str> {
+    if !command.name.starts_with("refs/") || !git2::Reference::is_valid_name(&command.name) {
+        return Err("funny refname");
+    }
+    if command.new.is_zero() {
+        return delete(repo, command);
+    }
+
+    if repo.git2.find_object(command.new, None).is_err() {
+        return Err("missing necessary objects");
+    }
+    if !command.old.is_zero() && command.old != command.new && !force {
+        match repo.git2.graph_descendant_of(command.new, command.old) {
+            Ok(true) => {}
+            _ => return Err("non-fast-forward"),
+        }
+    }
+    // libgit2 checks the old value while it holds the lock, so a push that
+    // raced ours gets told rather than overwritten
+    let message = "snootforge: push";
+    let updated = if command.old.is_zero() {
+        repo.git2
+            .reference(&command.name, command.new, false, message)
+    } else {
+        repo.git2
+            .reference_matching(&command.name, command.new, true, command.old, message)
+    };
+    updated.map(|_| ()).map_err(stale)
+}
+
+fn report(repo: &Repository, commands: &[Command], pack: &[u8]) -> Vec<u8> {
+    let unpacked = unpack(repo, pack);
+    let mut report = match &unpacked {
+        Ok(_) => pkt::line("unpack ok\n"),
+        Err(error) => pkt::line(&format!("unpack {}\n", error.message())),
+    };
+    let force = allows_non_fast_forward(repo);
+    // it'd only get overwritten at the next sync
+    let mirrored = mirror::get(repo).is_some();
+    for command in commands {
+        let updated = match unpacked {
+            Ok(_) if mirrored => Err("mirrors can't be pushed to"),
+            Ok(_) => update(repo, command, force),
+            Err(_) => Err("unpacker error"),
+        };
+        let status = match updated {
+            Ok(_) => format!("ok {}\n", command.name),
+            Err(reason) => format!("ng {} {}\n", command.name, reason),
+        };
+        report.append(&mut pkt::line(&status));
+    }
+    report.extend_from_slice(pkt::FLUSH);
+    report
+}
+
+pub fn receive_pack(repo: &Repository, request: &[u8]) -> Result<Vec<u8>, Missing> {
+    let (commands, pack) = commands(request)?;
+    // git sends an empty request first to check we'll let it in
+    if commands.is_empty() {
+        return Ok(vec![]);
+    }
+    Ok(report(repo, &commands, pack))
+}
+
+// for ssh, where the pack is everything after the commands until the client
+// hangs up
+pub fn serve<S: Read + Write>(repo: &Repository, stream: &mut S) -> io::Result<()> {
+    stream.write_all(&advertisement(repo)?)?;
+    let request = match pkt::read_request(stream)? {
+        Some(request) => request,
+        None => return Ok(()),
+    };
+    let (commands, _) = commands(&request)?;
+    if commands.is_empty() {
+        return Ok(());
+    }
+    let mut pack = vec![];
+    // there's no pack at all if all they're doing is deleting
+    if commands.iter().any(|command| !command.new.is_zero()) {
+        stream.read_to_end(&mut pack)?;
+    }
+    stream.write_all(&report(repo, &commands, &pack))?;
+    stream.flush()
+}
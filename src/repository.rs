@@ -1,8 +1,26 @@
 use crate::missing::Missing;
 use crate::tree::{Tree, TreeEntry, TreeEntryKind};
 use chrono::prelude::*;
+use std::collections::VecDeque;
 use std::{fmt, fs, io, path, str};
 
+pub struct RefTip {
+    pub name: String,
+    pub id: git2::Oid,
+    // what an annotated tag points to, for the ^{} lines
+    pub peeled: Option<git2::Oid>,
+}
+
+// one page of a log
+pub struct LogPage<'repo> {
+    pub commits: Vec<git2::Commit<'repo>>,
+    // what `after` is for the page before: None when this is the first page,
+    // and Some(None) when the one before is
+    pub previous: Option<Option<git2::Oid>>,
+    // what `after` is for the page after, if there are any more
+    pub next: Option<git2::Oid>,
+}
+
 pub struct Repository {
     // TODO make this unnesc
     pub git2: git2::Repository,
@@ -61,6 +79,25 @@ impl Repository {
         }
     }
 
+    // `/user/repo.git`, `/user/repo` or `user/repo`, the way git:// and ssh
+    // clients ask for them
+    pub fn open_path(repo_path: &str) -> Result<Repository, Missing> {
+        let parts: Vec<&str> = repo_path.trim_matches('/').split('/').collect();
+        let (user_name, project_name) = match parts[..] {
+            [user_name, project_name] => (user_name, project_name),
+            _ => return Err(Missing::Nowhere),
+        };
+        if user_name.starts_with('.') || project_name.starts_with('.') {
+            return Err(Missing::Nowhere);
+        }
+        let project_name = if project_name.ends_with(".git") {
+            &project_name[..project_name.len() - 4]
+        } else {
+            project_name
+        };
+        Repository::open_user_project(user_name, project_name)
+    }
+
     pub fn head(&self) -> Result<git2::Reference, Missing> {
         match self.git2.head() {
             Ok(head) => Ok(head),
@@ -81,6 +118,13 @@ impl Repository {
         Ok(Utc.timestamp(seconds_since_epoch, 0))
     }
 
+    // the same opt-in git-daemon uses
+    pub fn exported(&self) -> bool {
+        path::Path::new(&self.path)
+            .join("git-daemon-export-ok")
+            .exists()
+    }
+
     pub fn user_url(&self) -> String {
         format!("/{}", self.user_name)
     }
@@ -101,7 +145,48 @@ impl Repository {
             Err(_) => return Err(Missing::Nowhere),
         };
         walk.push(head_commit.id()).unwrap_or_default();
-        walk.set_sorting(git2::Sort::TOPOLOGICAL);
+        walk.set_sorting(git2::Sort::TOPOLOGICAL)
+            .unwrap_or_default();
+        let mut commits = vec![];
+        for commit in walk {
+            let commit = match commit {
+                Ok(commit) => commit,
+                Err(_) => return Err(Missing::Nowhere),
+            };
+            let commit = match self.git2.find_commit(commit) {
+                Ok(commit) => commit,
+                Err(_) => return Err(Missing::Nowhere),
+            };
+            commits.push(commit);
+        }
+        Ok(commits)
+    }
+
+    // a branch, tag or commit id, or anything else rev-parse takes
+    pub fn find_commit(&self, name: &str) -> Result<git2::Commit, Missing> {
+        match self.git2.revparse_single(name) {
+            Ok(object) => match object.peel_to_commit() {
+                Ok(commit) => Ok(commit),
+                Err(_) => Err(Missing::Nowhere),
+            },
+            Err(_) => Err(Missing::Nowhere),
+        }
+    }
+
+    // the commits in head that aren't in base, like `git log base..head`
+    pub fn range_log(
+        &self,
+        base: git2::Oid,
+        head: git2::Oid,
+    ) -> Result<Vec<git2::Commit>, Missing> {
+        let mut walk = match self.git2.revwalk() {
+            Ok(walk) => walk,
+            Err(_) => return Err(Missing::Nowhere),
+        };
+        walk.push(head).unwrap_or_default();
+        walk.hide(base).unwrap_or_default();
+        walk.set_sorting(git2::Sort::TOPOLOGICAL)
+            .unwrap_or_default();
         let mut commits = vec![];
         for commit in walk {
             let commit = match commit {
@@ -117,26 +202,136 @@ impl Repository {
         Ok(commits)
     }
 
-    fn get_refname(&self, refname: Option<&str>) -> Result<String, Missing> {
+    // the log from the ref, newest first, one commit at a time so nothing
+    // past what's shown gets read. left unsorted, which libgit2 walks in
+    // commit date order like `git log` does, since asking for any sorting
+    // makes it go through all of history first
+    fn walk(
+        &self,
+        refname: Option<&str>,
+    ) -> Result<impl Iterator<Item = Result<git2::Commit, Missing>> + '_, Missing> {
+        let refname = self.get_refname(refname)?;
+        let head_commit = match self.get_ref(&refname)?.peel_to_commit() {
+            Ok(commit) => commit,
+            Err(_) => return Err(Missing::Nowhere),
+        };
+        let mut walk = match self.git2.revwalk() {
+            Ok(walk) => walk,
+            Err(_) => return Err(Missing::Nowhere),
+        };
+        walk.push(head_commit.id()).unwrap_or_default();
+        Ok(walk.map(
+            move |id| match id.and_then(|id| self.git2.find_commit(id)) {
+                Ok(commit) => Ok(commit),
+                Err(_) => Err(Missing::Nowhere),
+            },
+        ))
+    }
+
+    // limit commits from the ref, starting after the one with that id
+    pub fn log_page(
+        &self,
+        refname: Option<&str>,
+        after: Option<git2::Oid>,
+        limit: usize,
+    ) -> Result<LogPage, Missing> {
+        paginate(self.walk(refname)?, after, limit)
+    }
+
+    // whether the commit changed what's at the path. path gets changed to
+    // where it was before when the commit moved it there
+    fn changed(&self, commit: &git2::Commit, path: &mut String) -> bool {
+        let id = entry_id(commit, path);
+        let mut parents = commit.parents();
+        let first_parent = match parents.next() {
+            Some(parent) => parent,
+            None => return id.is_some(),
+        };
+        // merges only count when they changed it compared to every parent
+        let unchanged = entry_id(&first_parent, path) == id
+            || parents.any(|parent| entry_id(&parent, path) == id);
+        if unchanged {
+            return false;
+        }
+        if id.is_some() && entry_id(&first_parent, path).is_none() {
+            if let Some(old_path) = self.renamed_from(&first_parent, commit, path) {
+                *path = old_path;
+            }
+        }
+        true
+    }
+
+    // the commits in the log that changed what's at the path, following it
+    // back through renames the way `git log --follow` does, a page at a time
+    pub fn path_log(
+        &self,
+        refname: Option<&str>,
+        path: &str,
+        after: Option<git2::Oid>,
+        limit: usize,
+    ) -> Result<LogPage, Missing> {
+        let mut path = path.trim_matches('/').to_owned();
+        let commits = self.walk(refname)?.filter(move |commit| match commit {
+            Ok(commit) => self.changed(commit, &mut path),
+            Err(_) => true,
+        });
+        paginate(commits, after, limit)
+    }
+
+    // where the file at path in the commit was in the parent, if it was moved
+    fn renamed_from(
+        &self,
+        parent: &git2::Commit,
+        commit: &git2::Commit,
+        path: &str,
+    ) -> Option<String> {
+        let mut diff = self
+            .git2
+            .diff_tree_to_tree(
+                parent.tree().ok().as_ref(),
+                commit.tree().ok().as_ref(),
+                None,
+            )
+            .ok()?;
+        let mut options = git2::DiffFindOptions::new();
+        options.renames(true);
+        diff.find_similar(Some(&mut options)).ok()?;
+        for delta in diff.deltas() {
+            if delta.status() != git2::Delta::Renamed {
+                continue;
+            }
+            if delta.new_file().path() == Some(path::Path::new(path)) {
+                return delta
+                    .old_file()
+                    .path()
+                    .and_then(|old_path| old_path.to_str())
+                    .map(|old_path| old_path.to_owned());
+            }
+        }
+        None
+    }
+
+    pub fn get_refname(&self, refname: Option<&str>) -> Result<String, Missing> {
         let head = self.head()?;
         let shorthead = head.shorthand().unwrap_or_default();
         Ok(refname.unwrap_or(shorthead).to_owned())
     }
 
-    fn get_ref(&self, refname: &str) -> Result<git2::Reference, Missing> {
+    pub fn get_ref(&self, refname: &str) -> Result<git2::Reference, Missing> {
         match self.git2.resolve_reference_from_short_name(refname) {
             Ok(reff) => Ok(reff),
             Err(_) => return Err(Missing::Nowhere),
         }
     }
 
-    pub fn tree(
+    // the git tree at a path in a ref, without any of the last commit
+    // business Tree::new does
+    pub fn git2_tree(
         &self,
-        refname: Option<&str>,
+        refname: &str,
         subpath: Option<&path::PathBuf>,
-    ) -> Result<crate::tree::Tree, Missing> {
-        let refname = self.get_refname(refname)?;
-        let reff = self.get_ref(&refname)?;
+    ) -> Result<git2::Tree, Missing> {
+        let reff = self.get_ref(refname)?;
         let tree = match reff.peel_to_tree() {
             Ok(tree) => tree,
             Err(_) => return Err(Missing::Nowhere),
@@ -164,7 +359,16 @@ impl Repository {
             }
             None => tree,
         };
+        Ok(tree)
+    }
 
+    pub fn tree(
+        &self,
+        refname: Option<&str>,
+        subpath: Option<&path::PathBuf>,
+    ) -> Result<crate::tree::Tree, Missing> {
+        let refname = self.get_refname(refname)?;
+        let tree = self.git2_tree(&refname, subpath)?;
         Tree::new(&refname, subpath, tree, &self)
     }
 
@@ -210,4 +414,207 @@ impl Repository {
             Err(Missing::Nowhere)
         }
     }
+
+    pub fn tips(&self) -> Result<Vec<RefTip>, Missing> {
+        self.tips_matching(&[])
+    }
+
+    // only the refs that start with one of the prefixes, or all of them if
+    // there aren't any prefixes
+    pub fn tips_matching(&self, prefixes: &[&str]) -> Result<Vec<RefTip>, Missing> {
+        let mut tips = vec![];
+        for reference in self.references()? {
+            let reference = match reference {
+                Ok(reference) => reference,
+                Err(_) => continue,
+            };
+            let name = match reference.name() {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            if !prefixes.is_empty() && !prefixes.iter().any(|prefix| name.starts_with(prefix)) {
+                continue;
+            }
+            let id = match reference.resolve() {
+                Ok(resolved) => match resolved.target() {
+                    Some(id) => id,
+                    None => continue,
+                },
+                Err(_) => continue,
+            };
+            let peeled = match self.git2.find_object(id, None) {
+                Ok(object) => match object.kind() {
+                    Some(git2::ObjectType::Tag) => match object.peel(git2::ObjectType::Any) {
+                        Ok(peeled) => Some(peeled.id()),
+                        Err(_) => None,
+                    },
+                    _ => None,
+                },
+                Err(_) => None,
+            };
+            tips.push(RefTip { name, id, peeled });
+        }
+        tips.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(tips)
+    }
+}
+
+// the page of the commits that starts after the one with that id, which
+// means going through the ones before it, but not the ones after the page
+fn paginate<'repo, I>(
+    mut commits: I,
+    after: Option<git2::Oid>,
+    limit: usize,
+) -> Result<LogPage<'repo>, Missing>
+where
+    I: Iterator<Item = Result<git2::Commit<'repo>, Missing>>,
+{
+    let mut previous = None;
+    if let Some(after) = after {
+        // the page before is the limit commits up to and including this
+        // one, so it starts after the one before those
+        let mut seen = VecDeque::new();
+        loop {
+            let commit = match commits.next() {
+                Some(commit) => commit?,
+                None => return Err(Missing::Nowhere),
+            };
+            seen.push_back(commit.id());
+            if seen.len() > limit + 1 {
+                seen.pop_front();
+            }
+            if commit.id() == after {
+                break;
+            }
+        }
+        previous = Some(if seen.len() > limit {
+            seen.front().cloned()
+        } else {
+            None
+        });
+    }
+    let mut page = vec![];
+    for commit in commits.by_ref().take(limit) {
+        page.push(commit?);
+    }
+    let next = match commits.next() {
+        Some(_) => page.last().map(|commit| commit.id()),
+        None => None,
+    };
+    Ok(LogPage {
+        commits: page,
+        previous,
+        next,
+    })
+}
+
+// the blob or tree at the path in the commit
+fn entry_id(commit: &git2::Commit, path: &str) -> Option<git2::Oid> {
+    let tree = commit.tree().ok()?;
+    if path.is_empty() {
+        return Some(tree.id());
+    }
+    tree.get_path(path::Path::new(path))
+        .ok()
+        .map(|entry| entry.id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // a line of commits, newest first like the log has them
+    fn history(name: &str, length: usize) -> (git2::Repository, Vec<git2::Oid>) {
+        let path = env::temp_dir().join(format!("snootforge-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&path).unwrap_or_default();
+        let repo = git2::Repository::init_bare(&path).unwrap();
+        let signature = git2::Signature::now("snoot", "snoot@example.com").unwrap();
+        let mut ids: Vec<git2::Oid> = vec![];
+        {
+            let tree_id = repo.treebuilder(None).unwrap().write().unwrap();
+            let tree = repo.find_tree(tree_id).unwrap();
+            for number in 0..length {
+                let parent = ids.last().map(|id| repo.find_commit(*id).unwrap());
+                let parents: Vec<&git2::Commit> = parent.iter().collect();
+                let id = repo
+                    .commit(
+                        None,
+                        &signature,
+                        &signature,
+                        &number.to_string(),
+                        &tree,
+                        &parents,
+                    )
+                    .unwrap();
+                ids.push(id);
+            }
+        }
+        ids.reverse();
+        (repo, ids)
+    }
+
+    fn log_page<'repo>(
+        repo: &'repo git2::Repository,
+        ids: &[git2::Oid],
+        after: Option<git2::Oid>,
+        limit: usize,
+    ) -> Result<LogPage<'repo>, Missing> {
+        let commits = ids.iter().map(|id| Ok(repo.find_commit(*id).unwrap()));
+        paginate(commits, after, limit)
+    }
+
+    fn page_ids(page: &LogPage) -> Vec<git2::Oid> {
+        page.commits.iter().map(|commit| commit.id()).collect()
+    }
+
+    #[test]
+    fn first_page() {
+        let (repo, ids) = history("first-page", 7);
+        let page = log_page(&repo, &ids, None, 2).unwrap();
+        assert_eq!(page_ids(&page), &ids[0..2]);
+        assert_eq!(page.previous, None);
+        assert_eq!(page.next, Some(ids[1]));
+    }
+
+    #[test]
+    fn middle_page() {
+        let (repo, ids) = history("middle-page", 7);
+        let page = log_page(&repo, &ids, Some(ids[3]), 2).unwrap();
+        assert_eq!(page_ids(&page), &ids[4..6]);
+        assert_eq!(page.previous, Some(Some(ids[1])));
+        assert_eq!(page.next, Some(ids[5]));
+        // the page before is the first one, which has no after
+        let page = log_page(&repo, &ids, Some(ids[1]), 2).unwrap();
+        assert_eq!(page_ids(&page), &ids[2..4]);
+        assert_eq!(page.previous, Some(None));
+    }
+
+    #[test]
+    fn last_page() {
+        let (repo, ids) = history("last-page", 7);
+        let page = log_page(&repo, &ids, Some(ids[4]), 2).unwrap();
+        assert_eq!(page_ids(&page), &ids[5..7]);
+        assert_eq!(page.previous, Some(Some(ids[2])));
+        assert_eq!(page.next, None);
+    }
+
+    #[test]
+    fn after_the_final_commit() {
+        let (repo, ids) = history("after-final", 7);
+        let page = log_page(&repo, &ids, Some(ids[6]), 2).unwrap();
+        assert!(page.commits.is_empty());
+        assert_eq!(page.previous, Some(Some(ids[4])));
+        assert_eq!(page.next, None);
+    }
+
+    #[test]
+    fn after_something_not_in_the_log() {
+        let (repo, ids) = history("after-unknown", 7);
+        let unknown = git2::Oid::hash_object(git2::ObjectType::Blob, b"not a commit").unwrap();
+        assert_eq!(
+            log_page(&repo, &ids, Some(unknown), 2).err(),
+            Some(Missing::Nowhere)
+        );
+    }
 }
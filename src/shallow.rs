@@ -0,0 +1,414 @@
+use crate::missing::Missing;
+use crate::repository::Repository;
+use std::collections::{BinaryHeap, HashSet, VecDeque};
+use std::io::Write;
+
+// shallow clones only get some of the history, partial clones only get some
+// of the files
+// https://git-scm.com/docs/shallow
+// https://git-scm.com/docs/partial-clone
+
+#[derive(Debug)]
+pub enum Deepen {
+    // how many commits back from each want, counting the want itself
+    Depth(usize),
+    // only the commits made since then, in seconds since the epoch
+    Since(i64),
+    // back to, but not including, anything these refs lead to
+    Not(Vec<String>),
+}
+
+#[derive(Debug)]
+pub enum Filter {
+    // no blobs at all, the client fetches them when it checks them out
+    BlobNone,
+    // only blobs smaller than this many bytes
+    BlobLimit(u64),
+}
+
+// https://git-scm.com/docs/git-rev-list#Documentation/git-rev-list.txt---filterltfilter-specgt
+pub fn parse_filter(spec: &str) -> Result<Filter, Missing> {
+    if spec == "blob:none" {
+        return Ok(Filter::BlobNone);
+    }
+    if !spec.starts_with("blob:limit=") {
+        return Err(Missing::Nowhere);
+    }
+    let limit = &spec["blob:limit=".len()..];
+    let (number, unit) = match limit.char_indices().last() {
+        Some((index, 'k')) | Some((index, 'K')) => (&limit[..index], 1024),
+        Some((index, 'm')) | Some((index, 'M')) => (&limit[..index], 1024 * 1024),
+        Some((index, 'g')) | Some((index, 'G')) => (&limit[..index], 1024 * 1024 * 1024),
+        _ => (limit, 1),
+    };
+    match number.parse::<u64>() {
+        Ok(number) => Ok(Filter::BlobLimit(number * unit)),
+        Err(_) => Err(Missing::Nowhere),
+    }
+}
+
+impl Filter {
+    fn allows(&self, size: usize) -> bool {
+        match self {
+            Filter::BlobNone => false,
+            Filter::BlobLimit(limit) => (size as u64) < *limit,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Shallow {
+    // the client's shallow commits, whose parents it doesn't have
+    pub client: HashSet<git2::Oid>,
+    // the commits whose parents it still won't have after this fetch
+    pub grafts: HashSet<git2::Oid>,
+    // what to tell the client changed
+    pub shallow: Vec<git2::Oid>,
+    pub unshallow: Vec<git2::Oid>,
+}
+
+impl Shallow {
+    // nothing to trim, so the plain revwalk will do
+    pub fn is_empty(&self) -> bool {
+        self.client.is_empty() && self.grafts.is_empty()
+    }
+}
+
+pub fn peel_commits(repo: &Repository, ids: &[git2::Oid]) -> Vec<git2::Oid> {
+    ids.iter()
+        .filter_map(|id| repo.git2.find_object(*id, None).ok())
+        .filter_map(|object| object.peel_to_commit().ok())
+        .map(|commit| commit.id())
+        .collect()
+}
+
+// every commit the deepen-not refs lead to, which is where the client's
+// history should stop
+fn excluded(repo: &Repository, names: &[String]) -> Result<HashSet<git2::Oid>, Missing> {
+    let git_error = |_| Missing::Nowhere;
+    let mut walk = repo.git2.revwalk().map_err(git_error)?;
+    for name in names {
+        let object = repo.git2.revparse_single(name).map_err(git_error)?;
+        let commit = object.peel_to_commit().map_err(git_error)?;
+        walk.push(commit.id()).map_err(git_error)?;
+    }
+    walk.map(|id| id.map_err(git_error)).collect()
+}
+
+// walks back from the starts until it runs out of depth or time, or reaches
+// the excluded history. the commits it stops at, that do have parents, are the
+// new shallow ones
+fn boundary(
+    repo: &Repository,
+    starts: &[git2::Oid],
+    deepen: &Deepen,
+) -> Result<(HashSet<git2::Oid>, HashSet<git2::Oid>), Missing> {
+    let git_error = |_| Missing::Nowhere;
+    let mut seen: HashSet<git2::Oid> = HashSet::new();
+    let mut boundary = HashSet::new();
+    let mut queue: VecDeque<(git2::Oid, usize)> = VecDeque::new();
+    let excluded = match deepen {
+        Deepen::Not(names) => excluded(repo, names)?,
+        _ => HashSet::new(),
+    };
+    for start in peel_commits(repo, starts) {
+        if seen.insert(start) {
+            queue.push_back((start, 1));
+        }
+    }
+    // breadth first, so every commit is seen at the shallowest depth it has
+    while let Some((id, depth)) = queue.pop_front() {
+        let commit = repo.git2.find_commit(id).map_err(git_error)?;
+        for parent in commit.parents() {
+            let deep_enough = match deepen {
+                Deepen::Depth(limit) => depth >= *limit,
+                Deepen::Since(since) => parent.time().seconds() < *since,
+                Deepen::Not(_) => excluded.contains(&parent.id()),
+            };
+            if deep_enough {
+                boundary.insert(id);
+            } else if seen.insert(parent.id()) {
+                queue.push_back((parent.id(), depth + 1));
+            }
+        }
+    }
+    Ok((seen, boundary))
+}
+
+// relative means `git fetch --deepen`, where the depth is counted back from
+// the client's shallow commits rather than from the wants
+pub fn shallow(
+    repo: &Repository,
+    wants: &[git2::Oid],
+    shallows: &[git2::Oid],
+    deepen: Option<&Deepen>,
+    relative: bool,
+) -> Result<Shallow, Missing> {
+    let client: HashSet<git2::Oid> = shallows.iter().cloned().collect();
+    let deepen = match deepen {
+        Some(deepen) => deepen,
+        None => {
+            return Ok(Shallow {
+                grafts: client.clone(),
+                client,
+                ..Shallow::default()
+            })
+        }
+    };
+    let (seen, boundary) = match deepen {
+        Deepen::Depth(depth) if relative => boundary(repo, shallows, &Deepen::Depth(depth + 1))?,
+        _ => boundary(repo, wants, deepen)?,
+    };
+    let mut shallow: Vec<git2::Oid> = boundary
+        .iter()
+        .filter(|id| !client.contains(id))
+        .cloned()
+        .collect();
+    let mut unshallow: Vec<git2::Oid> = client
+        .iter()
+        .filter(|id| seen.contains(id) && !boundary.contains(id))
+        .cloned()
+        .collect();
+    shallow.sort();
+    unshallow.sort();
+    let mut grafts = boundary;
+    for id in &client {
+        if !unshallow.contains(id) {
+            grafts.insert(*id);
+        }
+    }
+    Ok(Shallow {
+        client,
+        grafts,
+        shallow,
+        unshallow,
+    })
+}
+
+// the walk back from the wants and the client's common commits at once,
+// newest first like git's own, so it can stop as soon as everything left in
+// the queue is something the client has instead of going through all of its
+// history
+struct Walk<'a> {
+    repo: &'a Repository,
+    queue: BinaryHeap<(i64, git2::Oid)>,
+    queued: HashSet<git2::Oid>,
+    visited: HashSet<git2::Oid>,
+    // the commits the client has
+    has: HashSet<git2::Oid>,
+    // how many in the queue it hasn't
+    wanted: usize,
+}
+
+impl<'a> Walk<'a> {
+    fn push(&mut self, id: git2::Oid) -> Result<(), Missing> {
+        if self.visited.contains(&id) || !self.queued.insert(id) {
+            return Ok(());
+        }
+        let commit = self
+            .repo
+            .git2
+            .find_commit(id)
+            .map_err(|_| Missing::Nowhere)?;
+        self.queue.push((commit.time().seconds(), id));
+        if !self.has.contains(&id) {
+            self.wanted += 1;
+        }
+        Ok(())
+    }
+
+    fn mark(&mut self, id: git2::Oid) {
+        if self.has.insert(id) && self.queued.contains(&id) {
+            self.wanted -= 1;
+        }
+    }
+}
+
+// the commits to send, and the ones the client has right under them, whose
+// trees it has too. it doesn't go past the client's shallow commits, since it
+// hasn't got their parents
+fn walk<'a>(
+    repo: &'a Repository,
+    starts: &[git2::Oid],
+    common: &[git2::Oid],
+    shallow: &Shallow,
+) -> Result<(Vec<git2::Commit<'a>>, Vec<git2::Oid>), Missing> {
+    let mut walk = Walk {
+        repo,
+        queue: BinaryHeap::new(),
+        queued: HashSet::new(),
+        visited: HashSet::new(),
+        has: HashSet::new(),
+        wanted: 0,
+    };
+    for id in peel_commits(repo, common) {
+        walk.mark(id);
+        walk.push(id)?;
+    }
+    for id in starts {
+        walk.push(*id)?;
+    }
+
+    let mut sending = vec![];
+    while walk.wanted > 0 {
+        let id = match walk.queue.pop() {
+            Some((_, id)) => id,
+            None => break,
+        };
+        walk.queued.remove(&id);
+        walk.visited.insert(id);
+        let commit = repo.git2.find_commit(id).map_err(|_| Missing::Nowhere)?;
+        if walk.has.contains(&id) {
+            if !shallow.client.contains(&id) {
+                for parent in commit.parent_ids() {
+                    walk.mark(parent);
+                    walk.push(parent)?;
+                }
+            }
+            continue;
+        }
+        walk.wanted -= 1;
+        if !shallow.grafts.contains(&id) {
+            for parent in commit.parent_ids() {
+                walk.push(parent)?;
+            }
+        }
+        sending.push(commit);
+    }
+
+    let edges = sending
+        .iter()
+        .flat_map(|commit| commit.parent_ids())
+        .filter(|id| walk.has.contains(id))
+        .collect();
+    Ok((sending, edges))
+}
+
+// puts the tree and everything under it in the pack, minus what's already
+// been seen and the blobs the filter doesn't want
+fn insert_tree(
+    repo: &Repository,
+    builder: &mut git2::PackBuilder,
+    tree: git2::Oid,
+    filter: Option<&Filter>,
+    seen: &mut HashSet<git2::Oid>,
+) -> Result<(), Missing> {
+    let git_error = |_| Missing::Nowhere;
+    let odb = repo.git2.odb().map_err(git_error)?;
+    let mut stack = vec![tree];
+    while let Some(id) = stack.pop() {
+        if !seen.insert(id) {
+            continue;
+        }
+        builder.insert_object(id, None).map_err(git_error)?;
+        let tree = repo.git2.find_tree(id).map_err(git_error)?;
+        for entry in tree.iter() {
+            match entry.kind() {
+                Some(git2::ObjectType::Tree) => stack.push(entry.id()),
+                Some(git2::ObjectType::Blob) if !seen.contains(&entry.id()) => {
+                    seen.insert(entry.id());
+                    let wanted = match filter {
+                        Some(filter) => {
+                            let (size, _) = odb.read_header(entry.id()).map_err(git_error)?;
+                            filter.allows(size)
+                        }
+                        None => true,
+                    };
+                    if wanted {
+                        builder.insert_object(entry.id(), None).map_err(git_error)?;
+                    }
+                }
+                // submodules are commits in some other repo
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+// marks everything in a tree the client has as seen, without packing it
+fn skip_tree(
+    repo: &Repository,
+    tree: git2::Oid,
+    seen: &mut HashSet<git2::Oid>,
+) -> Result<(), Missing> {
+    let mut stack = vec![tree];
+    while let Some(id) = stack.pop() {
+        if !seen.insert(id) {
+            continue;
+        }
+        let tree = repo.git2.find_tree(id).map_err(|_| Missing::Nowhere)?;
+        for entry in tree.iter() {
+            match entry.kind() {
+                Some(git2::ObjectType::Tree) => stack.push(entry.id()),
+                _ => {
+                    seen.insert(entry.id());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// like upload_pack::pack, but walking the history by hand so it can stop at
+// the shallow commits and leave blobs out
+pub fn pack(
+    repo: &Repository,
+    wants: &[git2::Oid],
+    common: &[git2::Oid],
+    shallow: &Shallow,
+    filter: Option<&Filter>,
+    output: &mut dyn Write,
+) -> Result<(), Missing> {
+    let git_error = |_| Missing::Nowhere;
+    let mut builder = repo.git2.packbuilder().map_err(git_error)?;
+    let mut seen = HashSet::new();
+    // the client has the commits it's being unshallowed at, but not their
+    // parents, and the walk from the wants would stop before getting there
+    let mut commits = vec![];
+    for id in &shallow.unshallow {
+        let commit = repo.git2.find_commit(*id).map_err(git_error)?;
+        commits.extend(commit.parent_ids());
+    }
+
+    for want in wants {
+        let object = repo.git2.find_object(*want, None).map_err(git_error)?;
+        let object = match object.kind() {
+            Some(git2::ObjectType::Tag) => {
+                builder.insert_object(*want, None).map_err(git_error)?;
+                object.peel(git2::ObjectType::Any).map_err(git_error)?
+            }
+            _ => object,
+        };
+        match object.kind() {
+            Some(git2::ObjectType::Commit) => commits.push(object.id()),
+            Some(git2::ObjectType::Tree) => {
+                insert_tree(repo, &mut builder, object.id(), filter, &mut seen)?
+            }
+            // blobs they ask for by name get sent whatever the filter says,
+            // that's how partial clones fill themselves in
+            _ => builder
+                .insert_object(object.id(), None)
+                .map_err(git_error)?,
+        }
+    }
+
+    // the client has the trees of the commits it already has, so leave out
+    // whatever's in those
+    let (sending, edges) = walk(repo, &commits, common, shallow)?;
+    for id in edges {
+        let commit = repo.git2.find_commit(id).map_err(git_error)?;
+        skip_tree(repo, commit.tree_id(), &mut seen)?;
+    }
+
+    for commit in sending {
+        builder
+            .insert_object(commit.id(), None)
+            .map_err(git_error)?;
+        insert_tree(repo, &mut builder, commit.tree_id(), filter, &mut seen)?;
+    }
+
+    builder
+        .foreach(|chunk| output.write_all(chunk).is_ok())
+        .map_err(git_error)
+}
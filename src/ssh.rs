@@ -0,0 +1,301 @@
+use crate::missing::Missing;
+use crate::receive_pack;
+use crate::repository::Repository;
+use crate::upload_pack;
+use crate::user;
+use futures::future::Either;
+use futures::sync::mpsc as futures_mpsc;
+use futures::{Future, Sink, Stream};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::{mpsc, Arc};
+use std::{thread, time};
+use thrussh::server::{self, Auth, Session};
+use thrussh::{ChannelId, CryptoVec, MethodSet};
+use thrussh_keys::key;
+
+// make one with `ssh-keygen -t ed25519 -N '' -f ssh_host_ed25519_key`
+static HOST_KEY_PATH: &str = "./ssh_host_ed25519_key";
+
+// how many writes the worker can get ahead of the client by before it waits
+const OUTPUT_BUFFER: usize = 16;
+
+// what the thread running upload-pack or receive-pack tells the channel
+enum Event {
+    Output(Vec<u8>),
+    Finished(io::Result<()>),
+}
+
+// upload_pack::serve and receive_pack::serve want a connection they can block
+// on, so they get this end of a pair of channels. the ssh handler feeds the
+// input whenever data comes in, and the event loop sends the output on
+struct Pipe {
+    input: mpsc::Receiver<Vec<u8>>,
+    buffer: Vec<u8>,
+    output: futures::sink::Wait<futures_mpsc::Sender<Event>>,
+    closed: bool,
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer.is_empty() {
+            if self.closed {
+                return Ok(0);
+            }
+            match self.input.recv() {
+                Ok(chunk) => self.buffer = chunk,
+                // the client sent eof
+                Err(_) => {
+                    self.closed = true;
+                    return Ok(0);
+                }
+            }
+        }
+        let length = buf.len().min(self.buffer.len());
+        buf[..length].copy_from_slice(&self.buffer[..length]);
+        self.buffer.drain(..length);
+        Ok(length)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.output.send(Event::Output(buf.to_vec())) {
+            Ok(_) => Ok(buf.len()),
+            Err(_) => Err(io::Error::new(io::ErrorKind::BrokenPipe, "channel closed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn find_user(public_key: &key::PublicKey) -> Option<String> {
+    for user_name in user::names() {
+        for key in user::authorized_keys(&user_name) {
+            if let Ok(key) = thrussh_keys::parse_public_key_base64(&key) {
+                if &key == public_key {
+                    return Some(user_name);
+                }
+            }
+        }
+    }
+    None
+}
+
+// the worker's input, and what it says back
+fn start(
+    user_name: Option<&str>,
+    command: &str,
+    git_protocol: Option<&str>,
+) -> Result<(mpsc::Sender<Vec<u8>>, futures_mpsc::Receiver<Event>), Missing> {
+    let user_name = match user_name {
+        Some(user_name) => user_name,
+        None => return Err(Missing::Unwelcome),
+    };
+    // git runs `git-upload-pack '/user/repo.git'` on the other end
+    let mut words = command.splitn(2, ' ');
+    let (service, path) = match (words.next(), words.next()) {
+        (Some(service), Some(path)) => (service.to_owned(), path.trim_matches('\'')),
+        _ => return Err(Missing::Nowhere),
+    };
+    let repo = Repository::open_path(path)?;
+    match service.as_str() {
+        upload_pack::SERVICE => {}
+        receive_pack::SERVICE if repo.user_name == user_name => {}
+        receive_pack::SERVICE => return Err(Missing::Unwelcome),
+        _ => return Err(Missing::Nowhere),
+    }
+    let version = upload_pack::protocol_version(git_protocol);
+
+    let (input, input_receiver) = mpsc::channel();
+    let (events_sender, events) = futures_mpsc::channel(OUTPUT_BUFFER);
+    thread::spawn(move || {
+        let mut pipe = Pipe {
+            input: input_receiver,
+            buffer: vec![],
+            output: events_sender.wait(),
+            closed: false,
+        };
+        let result = match service.as_str() {
+            upload_pack::SERVICE => upload_pack::serve(&repo, &mut pipe, version),
+            _ => receive_pack::serve(&repo, &mut pipe),
+        };
+        pipe.output
+            .send(Event::Finished(result))
+            .unwrap_or_default();
+    });
+    Ok((input, events))
+}
+
+// passes along everything the worker says as it says it, then hangs up with
+// its exit status. this runs on the event loop, so the worker can take as
+// long as it likes building a pack without holding up anybody else
+fn forward(
+    channel: ChannelId,
+    handle: server::Handle,
+    events: futures_mpsc::Receiver<Event>,
+) -> impl Future<Item = (), Error = ()> {
+    events
+        .fold(handle, move |handle, event| match event {
+            Event::Output(data) => {
+                Either::A(handle.data(channel, None, CryptoVec::from_slice(&data)))
+            }
+            Event::Finished(result) => {
+                let exit_status = match result {
+                    Ok(_) => 0,
+                    Err(error) => {
+                        eprintln!("ssh went wrong: {}", error);
+                        1
+                    }
+                };
+                Either::B(
+                    handle
+                        .exit_status_request(channel, exit_status)
+                        .and_then(move |handle| handle.eof(channel))
+                        .and_then(move |handle| handle.close(channel)),
+                )
+            }
+        })
+        .map(|_| ())
+}
+
+#[derive(Clone)]
+struct Server;
+
+impl server::Server for Server {
+    type Handler = Handler;
+    fn new(&self) -> Handler {
+        Handler {
+            user_name: None,
+            inputs: HashMap::new(),
+            git_protocols: HashMap::new(),
+        }
+    }
+}
+
+struct Handler {
+    user_name: Option<String>,
+    // dropped when the client sends eof, which the worker reads as the end
+    inputs: HashMap<ChannelId, mpsc::Sender<Vec<u8>>>,
+    // what git sets GIT_PROTOCOL to for each channel, if it sent it before the
+    // command, which is how it asks for protocol v2
+    git_protocols: HashMap<ChannelId, String>,
+}
+
+impl server::Handler for Handler {
+    type Error = thrussh::Error;
+    type FutureAuth = futures::Finished<(Self, Auth), Self::Error>;
+    type FutureUnit = futures::Finished<(Self, Session), Self::Error>;
+    type FutureBool = futures::Finished<(Self, Session, bool), Self::Error>;
+
+    fn finished_auth(self, auth: Auth) -> Self::FutureAuth {
+        futures::finished((self, auth))
+    }
+
+    fn finished_bool(self, session: Session, b: bool) -> Self::FutureBool {
+        futures::finished((self, session, b))
+    }
+
+    fn finished(self, session: Session) -> Self::FutureUnit {
+        futures::finished((self, session))
+    }
+
+    fn auth_publickey(mut self, _user: &str, public_key: &key::PublicKey) -> Self::FutureAuth {
+        self.user_name = find_user(public_key);
+        let auth = match self.user_name {
+            Some(_) => Auth::Accept,
+            None => Auth::Reject,
+        };
+        self.finished_auth(auth)
+    }
+
+    fn env_request(
+        mut self,
+        channel: ChannelId,
+        variable_name: &str,
+        variable_value: &str,
+        session: Session,
+    ) -> Self::FutureUnit {
+        if variable_name == "GIT_PROTOCOL" {
+            self.git_protocols
+                .insert(channel, variable_value.to_owned());
+        }
+        self.finished(session)
+    }
+
+    fn exec_request(
+        mut self,
+        channel: ChannelId,
+        data: &[u8],
+        mut session: Session,
+    ) -> Self::FutureUnit {
+        let command = String::from_utf8_lossy(data).into_owned();
+        let git_protocol = self.git_protocols.remove(&channel);
+        match start(
+            self.user_name.as_ref().map(|n| &**n),
+            &command,
+            git_protocol.as_ref().map(|p| &**p),
+        ) {
+            Ok((input, events)) => {
+                self.inputs.insert(channel, input);
+                hyper::rt::spawn(forward(channel, session.handle(), events));
+                self.finished(session)
+            }
+            Err(missing) => {
+                let message = match missing {
+                    Missing::Unwelcome => "you can't push to somebody else's repo\n",
+                    _ => "that's not a repo, or not something git does\n",
+                };
+                // 1 is stderr
+                session.data(channel, Some(1), message.as_bytes());
+                session.exit_status_request(channel, 1);
+                session.eof(channel);
+                session.close(channel);
+                self.finished(session)
+            }
+        }
+    }
+
+    fn data(self, channel: ChannelId, data: &[u8], session: Session) -> Self::FutureUnit {
+        // an empty chunk would look like eof to the worker
+        match self.inputs.get(&channel) {
+            Some(input) if !data.is_empty() => input.send(data.to_vec()).unwrap_or_default(),
+            _ => {}
+        }
+        self.finished(session)
+    }
+
+    fn channel_eof(mut self, channel: ChannelId, session: Session) -> Self::FutureUnit {
+        self.inputs.remove(&channel);
+        self.finished(session)
+    }
+
+    fn channel_close(mut self, channel: ChannelId, session: Session) -> Self::FutureUnit {
+        self.inputs.remove(&channel);
+        self.git_protocols.remove(&channel);
+        self.finished(session)
+    }
+}
+
+pub fn listen(port: u16) -> io::Result<()> {
+    let host_key = match thrussh_keys::load_secret_key(HOST_KEY_PATH, None) {
+        Ok(host_key) => host_key,
+        Err(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("i couldn't load the ssh host key from {}", HOST_KEY_PATH),
+            ))
+        }
+    };
+    let mut config = server::Config::default();
+    config.keys.push(host_key);
+    config.methods = MethodSet::PUBLICKEY;
+    config.auth_rejection_time = time::Duration::from_secs(1);
+    let config = Arc::new(config);
+    let address = format!("0.0.0.0:{}", port);
+    eprintln!("Listening for ssh on port {}", port);
+    thread::spawn(move || server::run(config, &address, Server));
+    Ok(())
+}
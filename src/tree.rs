@@ -133,7 +133,7 @@ pub struct Tree<'a, 'b, 'c> {
     tree: git2::Tree<'a>,
     repo: &'c Repository,
     pub entries: Vec<TreeEntry<'b>>,
-    refname: String,
+    pub refname: String,
 }
 
 impl Tree<'_, '_, '_> {
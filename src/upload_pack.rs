@@ -0,0 +1,707 @@
+use crate::missing::Missing;
+use crate::pkt::{self, Packet};
+use crate::repository::Repository;
+use crate::shallow::{self, Deepen, Filter, Shallow};
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+
+// https://git-scm.com/docs/http-protocol#_smart_clients
+pub const SERVICE: &str = "git-upload-pack";
+pub const ADVERTISEMENT_TYPE: &str = "application/x-git-upload-pack-advertisement";
+pub const RESULT_TYPE: &str = "application/x-git-upload-pack-result";
+
+// multi_ack_detailed tells the client every have we know, so over http it
+// can send them again in the next request, and no-done lets the pack follow
+// straight on once we know enough. partial clones fetch the blobs they
+// skipped by id later, which v0 clients only do if we say they can
+const CAPABILITIES: &str = "multi_ack_detailed no-done side-band side-band-64k ofs-delta \
+                            no-progress shallow deepen-since deepen-not deepen-relative filter \
+                            allow-reachable-sha1-in-want";
+
+// https://git-scm.com/docs/protocol-v2#_capabilities
+const CAPABILITIES_V2: [&str; 2] = ["ls-refs", "fetch=shallow filter"];
+
+pub fn agent() -> String {
+    format!("agent=snootforge/{}", env!("CARGO_PKG_VERSION"))
+}
+
+// the Git-Protocol header looks like `version=2:some-other=thing`
+pub fn protocol_version(git_protocol: Option<&str>) -> u8 {
+    for parameter in git_protocol.unwrap_or("").split(':') {
+        match parameter {
+            "version=2" => return 2,
+            "version=1" => return 1,
+            _ => {}
+        }
+    }
+    0
+}
+
+pub fn advertisement(repo: &Repository) -> Result<Vec<u8>, Missing> {
+    let mut capabilities = CAPABILITIES.to_owned();
+    let mut lines: Vec<(git2::Oid, String)> = vec![];
+
+    if let Ok(head) = repo.head() {
+        if let (Some(id), Some(name)) = (head.target(), head.name()) {
+            lines.push((id, "HEAD".to_owned()));
+            capabilities += &format!(" symref=HEAD:{}", name);
+        }
+    }
+
+    for tip in repo.tips()? {
+        lines.push((tip.id, tip.name.clone()));
+        if let Some(peeled) = tip.peeled {
+            lines.push((peeled, format!("{}^{{}}", tip.name)));
+        }
+    }
+
+    capabilities += " ";
+    capabilities += &agent();
+
+    Ok(pkt::advertisement(&lines, &capabilities))
+}
+
+pub fn capabilities_v2() -> Vec<u8> {
+    let mut output = pkt::line("version 2\n");
+    output.append(&mut pkt::line(&format!("{}\n", agent())));
+    for capability in CAPABILITIES_V2.iter() {
+        output.append(&mut pkt::line(&format!("{}\n", capability)));
+    }
+    output.extend_from_slice(pkt::FLUSH);
+    output
+}
+
+pub fn info_refs(repo: &Repository, version: u8) -> Result<Vec<u8>, Missing> {
+    // v2 goes straight into the capabilities, without the service line
+    if version == 2 {
+        return Ok(capabilities_v2());
+    }
+    let mut output = pkt::line(&format!("# service={}\n", SERVICE));
+    output.extend_from_slice(pkt::FLUSH);
+    output.append(&mut advertisement(repo)?);
+    Ok(output)
+}
+
+#[derive(Default)]
+struct Negotiation {
+    wants: Vec<git2::Oid>,
+    haves: Vec<git2::Oid>,
+    capabilities: Vec<String>,
+    // the commits the client is already shallow at
+    shallows: Vec<git2::Oid>,
+    deepen: Option<Deepen>,
+    deepen_relative: bool,
+    filter: Option<Filter>,
+    done: bool,
+}
+
+fn parse_id(id: &str) -> Result<git2::Oid, Missing> {
+    match git2::Oid::from_str(id) {
+        Ok(id) => Ok(id),
+        Err(_) => Err(Missing::Nowhere),
+    }
+}
+
+// the lines v0 requests and v2 fetch arguments have in common
+fn argument(negotiation: &mut Negotiation, line: &str) -> Result<(), Missing> {
+    let mut words = line.splitn(2, ' ');
+    match (words.next(), words.next()) {
+        (Some("want"), Some(id)) => negotiation.wants.push(parse_id(id)?),
+        (Some("have"), Some(id)) => negotiation.haves.push(parse_id(id)?),
+        (Some("shallow"), Some(id)) => negotiation.shallows.push(parse_id(id)?),
+        (Some("deepen"), Some(depth)) => match depth.parse() {
+            Ok(0) => {}
+            Ok(depth) => negotiation.deepen = Some(Deepen::Depth(depth)),
+            Err(_) => return Err(Missing::Nowhere),
+        },
+        (Some("deepen-since"), Some(since)) => match since.parse() {
+            Ok(since) => negotiation.deepen = Some(Deepen::Since(since)),
+            Err(_) => return Err(Missing::Nowhere),
+        },
+        // it can be sent more than once, for more than one ref
+        (Some("deepen-not"), Some(name)) => match &mut negotiation.deepen {
+            Some(Deepen::Not(names)) => names.push(name.to_owned()),
+            _ => negotiation.deepen = Some(Deepen::Not(vec![name.to_owned()])),
+        },
+        (Some("deepen-relative"), None) => negotiation.deepen_relative = true,
+        (Some("filter"), Some(spec)) => negotiation.filter = Some(shallow::parse_filter(spec)?),
+        (Some("done"), None) => negotiation.done = true,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn negotiation(request: &[u8]) -> Result<Negotiation, Missing> {
+    let mut negotiation = Negotiation::default();
+    for packet in pkt::decode(request)? {
+        let line = match packet {
+            Packet::Flush | Packet::Delim => continue,
+            _ => match packet.text() {
+                Some(line) => line,
+                None => return Err(Missing::Nowhere),
+            },
+        };
+        let mut words = line.split(' ');
+        match (words.next(), words.next()) {
+            (Some("want"), Some(id)) => {
+                if negotiation.wants.is_empty() {
+                    negotiation.capabilities = words.map(|word| word.to_owned()).collect();
+                }
+                negotiation.wants.push(parse_id(id)?);
+            }
+            _ => argument(&mut negotiation, line)?,
+        }
+    }
+    // v2 sends it as its own line, v0 as a capability
+    if negotiation
+        .capabilities
+        .iter()
+        .any(|c| c == "deepen-relative")
+    {
+        negotiation.deepen_relative = true;
+    }
+    Ok(negotiation)
+}
+
+// writes the pack out as the packbuilder makes it
+pub fn pack(
+    repo: &Repository,
+    wants: &[git2::Oid],
+    common: &[git2::Oid],
+    output: &mut dyn Write,
+) -> Result<(), Missing> {
+    let git_error = |_| Missing::Nowhere;
+    let mut builder = repo.git2.packbuilder().map_err(git_error)?;
+    let mut walk = repo.git2.revwalk().map_err(git_error)?;
+
+    for want in wants {
+        let object = repo.git2.find_object(*want, None).map_err(git_error)?;
+        match object.kind() {
+            Some(git2::ObjectType::Commit) => walk.push(*want).map_err(git_error)?,
+            Some(git2::ObjectType::Tag) => {
+                builder.insert_object(*want, None).map_err(git_error)?;
+                let peeled = object.peel(git2::ObjectType::Any).map_err(git_error)?;
+                match peeled.kind() {
+                    Some(git2::ObjectType::Commit) => walk.push(peeled.id()).map_err(git_error)?,
+                    _ => builder
+                        .insert_recursive(peeled.id(), None)
+                        .map_err(git_error)?,
+                }
+            }
+            _ => builder.insert_recursive(*want, None).map_err(git_error)?,
+        }
+    }
+    for have in common {
+        walk.hide(*have).map_err(git_error)?;
+    }
+    builder.insert_walk(&mut walk).map_err(git_error)?;
+    builder
+        .foreach(|chunk| output.write_all(chunk).is_ok())
+        .map_err(git_error)
+}
+
+// the client hanging up halfway is as good as anything else going wrong,
+// there's nobody left to tell
+fn send(output: &mut dyn Write, data: &[u8]) -> Result<(), Missing> {
+    output.write_all(data).map_err(|_| Missing::Nowhere)
+}
+
+// the plain revwalk unless there's history or blobs to leave out
+fn trimmed_pack(
+    repo: &Repository,
+    negotiation: &Negotiation,
+    common: &[git2::Oid],
+    shallow: &Shallow,
+    output: &mut dyn Write,
+) -> Result<(), Missing> {
+    match (&negotiation.filter, shallow.is_empty()) {
+        (None, true) => pack(repo, &negotiation.wants, common, output),
+        (filter, _) => shallow::pack(
+            repo,
+            &negotiation.wants,
+            common,
+            shallow,
+            filter.as_ref(),
+            output,
+        ),
+    }
+}
+
+fn get_shallow(repo: &Repository, negotiation: &Negotiation) -> Result<Shallow, Missing> {
+    shallow::shallow(
+        repo,
+        &negotiation.wants,
+        &negotiation.shallows,
+        negotiation.deepen.as_ref(),
+        negotiation.deepen_relative,
+    )
+}
+
+// which commits the client should graft, and which it can stop grafting
+fn shallow_info(shallow: &Shallow) -> Vec<u8> {
+    let mut output = vec![];
+    for id in &shallow.shallow {
+        output.append(&mut pkt::line(&format!("shallow {}\n", id)));
+    }
+    for id in &shallow.unshallow {
+        output.append(&mut pkt::line(&format!("unshallow {}\n", id)));
+    }
+    output
+}
+
+// a tree's entries, and theirs, ticked off the objects still to be found.
+// trees already looked through are skipped, since most of each commit's tree
+// is the same as its parent's
+fn find_in_tree(
+    repo: &Repository,
+    tree: git2::Oid,
+    searched: &mut HashSet<git2::Oid>,
+    unfound: &mut HashSet<git2::Oid>,
+) -> Result<(), Missing> {
+    unfound.remove(&tree);
+    if !searched.insert(tree) {
+        return Ok(());
+    }
+    let tree = repo.git2.find_tree(tree).map_err(|_| Missing::Nowhere)?;
+    for entry in tree.iter() {
+        match entry.kind() {
+            Some(git2::ObjectType::Tree) => find_in_tree(repo, entry.id(), searched, unfound)?,
+            // a submodule's commit isn't ours to give out
+            Some(git2::ObjectType::Blob) => {
+                unfound.remove(&entry.id());
+            }
+            _ => {}
+        }
+        if unfound.is_empty() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// allow-reachable-sha1-in-want means anything a ref leads to, which is how
+// partial clones get the blobs they skipped, but nothing that's only lying
+// around in the odb, like the commits of a force-pushed-over branch
+fn reachable(repo: &Repository, wants: &[git2::Oid]) -> Result<bool, Missing> {
+    let git_error = |_| Missing::Nowhere;
+    let mut tips = HashSet::new();
+    if let Ok(head) = repo.head() {
+        tips.extend(head.target());
+    }
+    for tip in repo.tips()? {
+        tips.insert(tip.id);
+        tips.extend(tip.peeled);
+    }
+    // a clone or fetch only wants tips, so there's nothing to walk
+    let mut unfound: HashSet<git2::Oid> = wants
+        .iter()
+        .filter(|want| !tips.contains(want))
+        .cloned()
+        .collect();
+    if unfound.is_empty() {
+        return Ok(true);
+    }
+    // a commit's reachable if a tip comes after it, which the commit graph
+    // can say without looking in any trees, and if none does it isn't going
+    // to turn up in one
+    let tip_commits = shallow::peel_commits(repo, &tips.iter().cloned().collect::<Vec<_>>());
+    for want in unfound.clone() {
+        if repo.git2.find_commit(want).is_err() {
+            continue;
+        }
+        let after = tip_commits
+            .iter()
+            .any(|tip| repo.git2.graph_descendant_of(*tip, want).unwrap_or(false));
+        if !after {
+            return Ok(false);
+        }
+        unfound.remove(&want);
+    }
+    // what's left are trees and blobs, which take looking through trees
+    if unfound.is_empty() {
+        return Ok(true);
+    }
+
+    let mut searched = HashSet::new();
+    let mut walk = repo.git2.revwalk().map_err(git_error)?;
+    for tip in &tips {
+        match repo
+            .git2
+            .find_object(*tip, None)
+            .map(|object| object.kind())
+        {
+            Ok(Some(git2::ObjectType::Commit)) => walk.push(*tip).map_err(git_error)?,
+            // a tag can point straight at a tree
+            Ok(Some(git2::ObjectType::Tree)) => {
+                find_in_tree(repo, *tip, &mut searched, &mut unfound)?
+            }
+            _ => {}
+        }
+    }
+    for id in walk {
+        if unfound.is_empty() {
+            break;
+        }
+        let commit = repo
+            .git2
+            .find_commit(id.map_err(git_error)?)
+            .map_err(git_error)?;
+        unfound.remove(&commit.id());
+        find_in_tree(repo, commit.tree_id(), &mut searched, &mut unfound)?;
+    }
+    Ok(unfound.is_empty())
+}
+
+// the v0 answers to the client's haves, the way upload-pack.c does them.
+// with multi_ack_detailed every have we've got gets `ACK <id> common`, and
+// `ACK <id> ready` once there's one under every want. without it there's one
+// plain ACK for the first, and NAKs until then
+struct Acks<'a> {
+    repo: &'a Repository,
+    // the wants there isn't a common commit under yet
+    wants: Vec<git2::Oid>,
+    detailed: bool,
+    no_done: bool,
+    common: Vec<git2::Oid>,
+    // what this round's haves turned out to be
+    got_common: bool,
+    got_other: bool,
+    ready: bool,
+}
+
+impl<'a> Acks<'a> {
+    fn new(repo: &'a Repository, negotiation: &Negotiation) -> Acks<'a> {
+        let has = |capability: &str| negotiation.capabilities.iter().any(|c| c == capability);
+        Acks {
+            repo,
+            wants: shallow::peel_commits(repo, &negotiation.wants),
+            detailed: has("multi_ack_detailed"),
+            no_done: has("no-done"),
+            common: vec![],
+            got_common: false,
+            got_other: false,
+            ready: false,
+        }
+    }
+
+    // whether more haves would make the pack any smaller
+    fn ok_to_give_up(&mut self) -> bool {
+        if self.common.is_empty() {
+            return false;
+        }
+        let repo = self.repo;
+        let common = &self.common;
+        self.wants.retain(|want| {
+            !common.iter().any(|have| {
+                have == want || repo.git2.graph_descendant_of(*want, *have).unwrap_or(false)
+            })
+        });
+        self.wants.is_empty()
+    }
+
+    fn have(&mut self, id: git2::Oid) -> Vec<u8> {
+        if self.repo.git2.find_commit(id).is_err() {
+            self.got_other = true;
+            if self.detailed && self.ok_to_give_up() {
+                self.ready = true;
+                return pkt::line(&format!("ACK {} ready\n", id));
+            }
+            return vec![];
+        }
+        self.got_common = true;
+        self.common.push(id);
+        if self.detailed {
+            pkt::line(&format!("ACK {} common\n", id))
+        } else if self.common.len() == 1 {
+            pkt::line(&format!("ACK {}\n", id))
+        } else {
+            vec![]
+        }
+    }
+
+    // the end of a round of haves, and whether the pack comes next without
+    // waiting for a done
+    fn flush(&mut self) -> (Vec<u8>, bool) {
+        let mut output = vec![];
+        let last = self.common.last().cloned();
+        if self.detailed && self.got_common && !self.got_other && self.ok_to_give_up() {
+            self.ready = true;
+            if let Some(last) = last {
+                output.append(&mut pkt::line(&format!("ACK {} ready\n", last)));
+            }
+        }
+        if self.common.is_empty() || self.detailed {
+            output.append(&mut pkt::line("NAK\n"));
+        }
+        if self.no_done && self.ready {
+            if let Some(last) = last {
+                output.append(&mut pkt::line(&format!("ACK {}\n", last)));
+                return (output, true);
+            }
+        }
+        self.got_common = false;
+        self.got_other = false;
+        (output, false)
+    }
+
+    fn done(&self) -> Vec<u8> {
+        match self.common.last() {
+            Some(last) if self.detailed => pkt::line(&format!("ACK {}\n", last)),
+            // the one plain ACK's already been sent
+            Some(_) => vec![],
+            None => pkt::line("NAK\n"),
+        }
+    }
+}
+
+// the haves we've got too, after checking they can have everything they want
+fn common(repo: &Repository, negotiation: &Negotiation) -> Result<Vec<git2::Oid>, Missing> {
+    if !reachable(repo, &negotiation.wants)? {
+        return Err(Missing::Nowhere);
+    }
+    Ok(negotiation
+        .haves
+        .iter()
+        .filter(|have| repo.git2.find_commit(**have).is_ok())
+        .cloned()
+        .collect())
+}
+
+// stateless-rpc, so each request is one round: the wants, then the haves,
+// which start with the ones we ACKed as common last time. nothing's written
+// until the request's been checked, so a bad one can still get a 404
+pub fn upload_pack(
+    repo: &Repository,
+    request: &[u8],
+    version: u8,
+    output: &mut dyn Write,
+) -> Result<(), Missing> {
+    if version == 2 {
+        return command(repo, request, output);
+    }
+    let negotiation = negotiation(request)?;
+    if negotiation.wants.is_empty() {
+        return Ok(());
+    }
+    if !reachable(repo, &negotiation.wants)? {
+        return Err(Missing::Nowhere);
+    }
+    let shallow = get_shallow(repo, &negotiation)?;
+
+    // every response to a deepening fetch starts with the shallow commits
+    if negotiation.deepen.is_some() {
+        send(output, &shallow_info(&shallow))?;
+        send(output, pkt::FLUSH)?;
+        // the first request is only the wants, to find that out
+        if negotiation.haves.is_empty() && !negotiation.done {
+            return Ok(());
+        }
+    }
+
+    let mut acks = Acks::new(repo, &negotiation);
+    let mut acknowledgements = vec![];
+    for have in &negotiation.haves {
+        acknowledgements.append(&mut acks.have(*have));
+    }
+    let ready = if negotiation.done {
+        acknowledgements.append(&mut acks.done());
+        true
+    } else {
+        let (mut end, ready) = acks.flush();
+        acknowledgements.append(&mut end);
+        ready
+    };
+    send(output, &acknowledgements)?;
+    if !ready {
+        return Ok(());
+    }
+
+    packfile(repo, &negotiation, &acks.common, &shallow, output)
+}
+
+// the pack, in the sideband they asked for
+fn packfile(
+    repo: &Repository,
+    negotiation: &Negotiation,
+    common: &[git2::Oid],
+    shallow: &Shallow,
+    output: &mut dyn Write,
+) -> Result<(), Missing> {
+    let has = |capability: &str| negotiation.capabilities.iter().any(|c| c == capability);
+    let size = if has("side-band-64k") {
+        pkt::SIDEBAND_64K
+    } else if has("side-band") {
+        pkt::SIDEBAND
+    } else {
+        return trimmed_pack(repo, negotiation, common, shallow, output);
+    };
+    let mut sideband = pkt::Sideband {
+        output,
+        band: 1,
+        size,
+    };
+    trimmed_pack(repo, negotiation, common, shallow, &mut sideband)?;
+    send(sideband.output, pkt::FLUSH)
+}
+
+// for git:// and ssh, where the whole conversation happens on one connection
+// instead of one request per round
+pub fn serve<S: Read + Write>(repo: &Repository, stream: &mut S, version: u8) -> io::Result<()> {
+    if version == 2 {
+        stream.write_all(&capabilities_v2())?;
+        while let Some(request) = pkt::read_request(stream)? {
+            command(repo, &request, stream)?;
+        }
+        return Ok(());
+    }
+
+    stream.write_all(&advertisement(repo)?)?;
+    // ls-remote just sends a flush and goes away
+    let wants = match pkt::read_request(stream)? {
+        Some(wants) => wants,
+        None => return Ok(()),
+    };
+    let negotiation = negotiation(&wants)?;
+    if negotiation.wants.is_empty() {
+        return Ok(());
+    }
+    if !reachable(repo, &negotiation.wants)? {
+        return Err(Missing::Nowhere.into());
+    }
+    let shallow = get_shallow(repo, &negotiation)?;
+    if negotiation.deepen.is_some() {
+        stream.write_all(&shallow_info(&shallow))?;
+        stream.write_all(pkt::FLUSH)?;
+    }
+
+    let mut acks = Acks::new(repo, &negotiation);
+    loop {
+        let packet = pkt::read_raw(stream)?;
+        let (packet, _) = pkt::read(&packet)?;
+        match packet {
+            Packet::Flush => {
+                let (end, ready) = acks.flush();
+                stream.write_all(&end)?;
+                if ready {
+                    break;
+                }
+            }
+            _ => match packet.text() {
+                Some("done") => {
+                    stream.write_all(&acks.done())?;
+                    break;
+                }
+                Some(line) if line.starts_with("have ") => {
+                    let id = parse_id(&line["have ".len()..])?;
+                    stream.write_all(&acks.have(id))?;
+                }
+                _ => {}
+            },
+        }
+    }
+
+    packfile(repo, &negotiation, &acks.common, &shallow, stream)?;
+    stream.flush()
+}
+
+// https://git-scm.com/docs/protocol-v2#_command_request
+fn command(repo: &Repository, request: &[u8], output: &mut dyn Write) -> Result<(), Missing> {
+    let mut command = None;
+    let mut arguments = vec![];
+    let mut in_arguments = false;
+    for packet in pkt::decode(request)? {
+        match packet {
+            Packet::Flush => break,
+            Packet::Delim => in_arguments = true,
+            _ => {
+                let line = match packet.text() {
+                    Some(line) => line,
+                    None => return Err(Missing::Nowhere),
+                };
+                if in_arguments {
+                    arguments.push(line);
+                } else if line.starts_with("command=") {
+                    command = Some(&line["command=".len()..]);
+                }
+            }
+        }
+    }
+    match command {
+        Some("ls-refs") => send(output, &ls_refs(repo, &arguments)?),
+        Some("fetch") => fetch(repo, &arguments, output),
+        // an empty request is just the client hanging up
+        None => Ok(()),
+        _ => Err(Missing::Nowhere),
+    }
+}
+
+fn ls_refs(repo: &Repository, arguments: &[&str]) -> Result<Vec<u8>, Missing> {
+    let peel = arguments.contains(&"peel");
+    let symrefs = arguments.contains(&"symrefs");
+    let prefixes: Vec<&str> = arguments
+        .iter()
+        .filter(|argument| argument.starts_with("ref-prefix "))
+        .map(|argument| &argument["ref-prefix ".len()..])
+        .collect();
+
+    let mut output = vec![];
+    if prefixes.is_empty() || prefixes.iter().any(|prefix| "HEAD".starts_with(prefix)) {
+        if let Ok(head) = repo.head() {
+            if let (Some(id), Some(name)) = (head.target(), head.name()) {
+                let line = if symrefs {
+                    format!("{} HEAD symref-target:{}\n", id, name)
+                } else {
+                    format!("{} HEAD\n", id)
+                };
+                output.append(&mut pkt::line(&line));
+            }
+        }
+    }
+    for tip in repo.tips_matching(&prefixes)? {
+        let line = match (peel, tip.peeled) {
+            (true, Some(peeled)) => format!("{} {} peeled:{}\n", tip.id, tip.name, peeled),
+            _ => format!("{} {}\n", tip.id, tip.name),
+        };
+        output.append(&mut pkt::line(&line));
+    }
+    output.extend_from_slice(pkt::FLUSH);
+    Ok(output)
+}
+
+// https://git-scm.com/docs/protocol-v2#_fetch
+fn fetch(repo: &Repository, arguments: &[&str], output: &mut dyn Write) -> Result<(), Missing> {
+    let mut negotiation = Negotiation::default();
+    for line in arguments {
+        argument(&mut negotiation, line)?;
+    }
+    let common = common(repo, &negotiation)?;
+
+    if !negotiation.done {
+        send(output, &pkt::line("acknowledgments\n"))?;
+        if common.is_empty() {
+            send(output, &pkt::line("NAK\n"))?;
+            return send(output, pkt::FLUSH);
+        }
+        for id in &common {
+            send(output, &pkt::line(&format!("ACK {}\n", id)))?;
+        }
+        send(output, &pkt::line("ready\n"))?;
+        send(output, pkt::DELIM)?;
+    }
+
+    let shallow = get_shallow(repo, &negotiation)?;
+    if negotiation.deepen.is_some() || !shallow.client.is_empty() {
+        send(output, &pkt::line("shallow-info\n"))?;
+        send(output, &shallow_info(&shallow))?;
+        send(output, pkt::DELIM)?;
+    }
+
+    send(output, &pkt::line("packfile\n"))?;
+    let mut sideband = pkt::Sideband {
+        output,
+        band: 1,
+        size: pkt::SIDEBAND_64K,
+    };
+    trimmed_pack(repo, &negotiation, &common, &shallow, &mut sideband)?;
+    send(sideband.output, pkt::FLUSH)
+}
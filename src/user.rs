@@ -1,7 +1,16 @@
 use crate::missing::Missing;
 use crate::repository::Repository;
+use sha2::{Digest, Sha256};
 use std::{fs, path};
 
+// each line of a user's passwd file is `salt:iterations:hex(key)`, where the
+// key is PBKDF2-HMAC-SHA256 of the password, so
+// `python3 -c 'import hashlib; print(hashlib.pbkdf2_hmac("sha256", b"password", b"salt", 100000).hex())'`
+// makes one
+static PASSWD_FILE_NAME: &str = "passwd";
+// the same format as ~/.ssh/authorized_keys
+static AUTHORIZED_KEYS_FILE_NAME: &str = "authorized_keys";
+
 #[derive(Debug)]
 pub struct User {
     pub name: String,
@@ -31,6 +40,9 @@ impl User {
         for bare in dir {
             let bare: fs::DirEntry = bare.expect("item wasn't anything");
             let path = bare.path();
+            if !path.is_dir() {
+                continue;
+            }
             let repo = Repository::open(&name, &path);
             if let Err(error) = repo {
                 eprintln!("not including {:?} because {:?}", bare, error);
@@ -45,3 +57,133 @@ impl User {
         format!("/{}", self.name)
     }
 }
+
+const BLOCK_SIZE: usize = 64;
+
+// https://tools.ietf.org/html/rfc2104
+fn hmac(key: &[u8], message: &[&[u8]]) -> Vec<u8> {
+    let mut block = [0; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.input(block.iter().map(|byte| byte ^ 0x36).collect::<Vec<u8>>());
+    for part in message {
+        inner.input(part);
+    }
+    let mut outer = Sha256::new();
+    outer.input(block.iter().map(|byte| byte ^ 0x5c).collect::<Vec<u8>>());
+    outer.input(inner.result());
+    outer.result().to_vec()
+}
+
+// https://tools.ietf.org/html/rfc8018#section-5.2, for one block of output,
+// which is all a sha256 sized key needs
+fn hash_password(salt: &str, iterations: u32, password: &str) -> Vec<u8> {
+    let password = password.as_bytes();
+    let mut round = hmac(password, &[salt.as_bytes(), &1u32.to_be_bytes()]);
+    let mut key = round.clone();
+    for _ in 1..iterations {
+        round = hmac(password, &[&round]);
+        for (byte, round_byte) in key.iter_mut().zip(&round) {
+            *byte ^= round_byte;
+        }
+    }
+    key
+}
+
+// an odd digit at the end has no pair, so the whole thing is None
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|start| u8::from_str_radix(hex.get(start..start + 2)?, 16).ok())
+        .collect()
+}
+
+// looks at every byte whatever the first difference, so how long it takes
+// doesn't say how much of the hash was right
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len()
+        && left
+            .iter()
+            .zip(right)
+            .fold(0, |difference, (left, right)| difference | (left ^ right))
+            == 0
+}
+
+// checks a basic Authorization header against the user's passwd file
+pub fn authorize(user_name: &str, authorization: Option<&str>) -> Result<(), Missing> {
+    let credentials = match authorization {
+        Some(authorization) if authorization.starts_with("Basic ") => &authorization[6..],
+        _ => return Err(Missing::Unwelcome),
+    };
+    let credentials = match base64::decode(credentials.trim()) {
+        Ok(credentials) => match String::from_utf8(credentials) {
+            Ok(credentials) => credentials,
+            Err(_) => return Err(Missing::Unwelcome),
+        },
+        Err(_) => return Err(Missing::Unwelcome),
+    };
+    let mut credentials = credentials.splitn(2, ':');
+    let (name, password) = match (credentials.next(), credentials.next()) {
+        (Some(name), Some(password)) => (name, password),
+        _ => return Err(Missing::Unwelcome),
+    };
+    if name != user_name {
+        return Err(Missing::Unwelcome);
+    }
+
+    let mut passwd_path = super::get_git_root();
+    passwd_path.push(user_name);
+    passwd_path.push(PASSWD_FILE_NAME);
+    let passwd = match fs::read_to_string(passwd_path) {
+        Ok(passwd) => passwd,
+        Err(_) => return Err(Missing::Unwelcome),
+    };
+    for line in passwd.lines() {
+        let mut line = line.trim().splitn(3, ':');
+        if let (Some(salt), Some(iterations), Some(hash)) = (line.next(), line.next(), line.next())
+        {
+            if let (Ok(iterations), Some(hash)) = (iterations.parse(), parse_hex(hash)) {
+                if constant_time_eq(&hash_password(salt, iterations, password), &hash) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+    Err(Missing::Unwelcome)
+}
+
+pub fn names() -> Vec<String> {
+    match fs::read_dir(super::get_git_root()) {
+        Ok(user_dirs) => user_dirs
+            .filter_map(|user_dir| user_dir.ok())
+            .filter(|user_dir| user_dir.path().is_dir())
+            .filter_map(|user_dir| user_dir.file_name().into_string().ok())
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+// just the base64 part of each key, skipping any options and the comment
+pub fn authorized_keys(user_name: &str) -> Vec<String> {
+    let mut keys_path = super::get_git_root();
+    keys_path.push(user_name);
+    keys_path.push(AUTHORIZED_KEYS_FILE_NAME);
+    let authorized_keys = match fs::read_to_string(keys_path) {
+        Ok(authorized_keys) => authorized_keys,
+        Err(_) => return vec![],
+    };
+    let mut keys = vec![];
+    for line in authorized_keys.lines() {
+        let mut words = line
+            .split_whitespace()
+            .skip_while(|word| !word.starts_with("ssh-") && !word.starts_with("ecdsa-"));
+        if let (Some(_kind), Some(key)) = (words.next(), words.next()) {
+            keys.push(key.to_owned());
+        }
+    }
+    keys
+}
@@ -0,0 +1,128 @@
+#![allow(dead_code)]
+// starting snootforge and running git, for the tests that need a real one of
+// each
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::{env, fs, thread, time};
+
+pub struct Server {
+    child: Child,
+    pub port: u16,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.child.kill().unwrap_or_default();
+    }
+}
+
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn wait_for(port: u16) {
+    for _ in 0..100 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            break;
+        }
+        thread::sleep(time::Duration::from_millis(50));
+    }
+}
+
+// the extra args are flags like `--git-port`, and it waits for the ports in
+// them to be listening too. it runs next to the git root, so whatever it keeps
+// in its working directory, like the ssh host key, goes there
+pub fn serve_with(git_root: &Path, args: &[&str], ports: &[u16]) -> Server {
+    let port = free_port();
+    let child = Command::new(env!("CARGO_BIN_EXE_snootforge"))
+        .arg(git_root)
+        .arg(port.to_string())
+        .args(args)
+        .current_dir(git_root.parent().unwrap())
+        .spawn()
+        .expect("couldn't start snootforge");
+    wait_for(port);
+    for port in ports {
+        wait_for(*port);
+    }
+    Server { child, port }
+}
+
+pub fn serve(git_root: &Path) -> Server {
+    serve_with(git_root, &[], &[])
+}
+
+pub fn git_with_env(directory: &Path, args: &[&str], envs: &[(&str, &str)]) -> String {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(directory)
+        .env("GIT_AUTHOR_NAME", "snoot")
+        .env("GIT_AUTHOR_EMAIL", "snoot@example.com")
+        .env("GIT_COMMITTER_NAME", "snoot")
+        .env("GIT_COMMITTER_EMAIL", "snoot@example.com")
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .envs(envs.iter().cloned())
+        .output()
+        .expect("couldn't run git");
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+pub fn git(directory: &Path, args: &[&str]) -> String {
+    git_with_env(directory, args, &[])
+}
+
+pub fn scratch(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("snootforge-{}-{}", name, std::process::id()));
+    fs::remove_dir_all(&path).unwrap_or_default();
+    fs::create_dir_all(&path).unwrap();
+    path
+}
+
+// a bare repo at snoot/<name>.git with a couple of commits on main, made in
+// work
+pub fn bare_repo(git_root: &Path, work: &Path, name: &str) -> PathBuf {
+    fs::create_dir_all(work).unwrap();
+    git(work, &["init", "--quiet", "--initial-branch=main"]);
+    for number in 0..2 {
+        fs::write(work.join("file"), number.to_string()).unwrap();
+        git(work, &["add", "file"]);
+        git(
+            work,
+            &["commit", "--quiet", "--message", &number.to_string()],
+        );
+    }
+    let bare = git_root.join("snoot").join(format!("{}.git", name));
+    fs::create_dir_all(&bare).unwrap();
+    git(
+        git_root,
+        &[
+            "clone",
+            "--quiet",
+            "--bare",
+            work.to_str().unwrap(),
+            bare.to_str().unwrap(),
+        ],
+    );
+    bare
+}
+
+pub fn refs(repo: &Path) -> String {
+    git(
+        repo,
+        &[
+            "for-each-ref",
+            "--format=%(objectname) %(refname:lstrip=-1)",
+        ],
+    )
+}
@@ -0,0 +1,50 @@
+// clones over git:// with a real git, in both protocol versions, and checks
+// repos without git-daemon-export-ok stay hidden
+mod common;
+
+use common::{bare_repo, free_port, git, scratch, serve_with};
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn clones_exported_repos() {
+    let scratch = scratch("daemon");
+    let git_root = scratch.join("root");
+    let bare = bare_repo(&git_root, &scratch.join("work"), "exported");
+    fs::write(bare.join("git-daemon-export-ok"), "").unwrap();
+    bare_repo(&git_root, &scratch.join("hidden-work"), "hidden");
+    let git_port = free_port();
+    let _server = serve_with(
+        &git_root,
+        &["--git-port", &git_port.to_string()],
+        &[git_port],
+    );
+
+    for version in &["0", "2"] {
+        let url = format!("git://127.0.0.1:{}/snoot/exported.git", git_port);
+        let clone = format!("clone-v{}", version);
+        git(
+            &scratch,
+            &[
+                "-c",
+                &format!("protocol.version={}", version),
+                "clone",
+                "--quiet",
+                &url,
+                &clone,
+            ],
+        );
+        let clone = scratch.join(clone);
+        git(&clone, &["fsck", "--strict"]);
+        assert_eq!(git(&clone, &["log", "--format=%s"]), "1\n0\n");
+    }
+
+    let url = format!("git://127.0.0.1:{}/snoot/hidden.git", git_port);
+    let status = Command::new("git")
+        .args(["clone", "--quiet", &url, "hidden"])
+        .current_dir(&scratch)
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    fs::remove_dir_all(&scratch).unwrap_or_default();
+}
@@ -0,0 +1,124 @@
+// clones fixture repos through the dumb http protocol with a real git, to
+// make sure what we serve is what `git update-server-info` would have made
+mod common;
+
+use common::{scratch, serve};
+use std::fs;
+use std::path::Path;
+
+fn git(directory: &Path, args: &[&str]) -> String {
+    common::git_with_env(directory, args, &[("GIT_SMART_HTTP", "0")])
+}
+
+// a repo with loose objects, a pack, an annotated tag and a symbolic ref
+fn fixture(git_root: &Path, work: &Path) {
+    let user_root = git_root.join("snoot");
+    fs::create_dir_all(&user_root).unwrap();
+    git(work, &["init", "--quiet", "--initial-branch=main"]);
+    for number in 0..3 {
+        fs::write(work.join("file"), number.to_string()).unwrap();
+        git(work, &["add", "file"]);
+        git(
+            work,
+            &["commit", "--quiet", "--message", &number.to_string()],
+        );
+        if number == 1 {
+            git(work, &["tag", "--annotate", "v1", "--message", "one"]);
+        }
+    }
+    let bare = user_root.join("fixture.git");
+    git(
+        git_root,
+        &[
+            "clone",
+            "--quiet",
+            "--bare",
+            work.to_str().unwrap(),
+            bare.to_str().unwrap(),
+        ],
+    );
+    git(&bare, &["repack", "-a", "-d", "--quiet"]);
+    fs::write(work.join("file"), "loose").unwrap();
+    git(work, &["commit", "--quiet", "--all", "--message", "loose"]);
+    git(
+        work,
+        &["push", "--quiet", bare.to_str().unwrap(), "main", "v1"],
+    );
+    git(
+        &bare,
+        &["symbolic-ref", "refs/heads/alias", "refs/heads/main"],
+    );
+}
+
+#[test]
+fn clones_packs_loose_objects_and_tags() {
+    let scratch = scratch("dumb");
+    let git_root = scratch.join("root");
+    let work = scratch.join("work");
+    fs::create_dir_all(&work).unwrap();
+    fixture(&git_root, &work);
+    let server = serve(&git_root);
+
+    let url = format!("http://127.0.0.1:{}/snoot/fixture", server.port);
+    git(&scratch, &["clone", "--quiet", &url, "clone"]);
+    let clone = scratch.join("clone");
+    git(&clone, &["fsck", "--strict"]);
+
+    let log = git(&clone, &["log", "--format=%s", "origin/main"]);
+    assert_eq!(log, "loose\n2\n1\n0\n");
+    let tag = git(&clone, &["cat-file", "-t", "v1"]);
+    assert_eq!(tag, "tag\n");
+    assert_eq!(
+        git(&clone, &["rev-parse", "origin/alias"]),
+        git(&clone, &["rev-parse", "origin/main"])
+    );
+
+    let info_refs = git(&scratch, &["ls-remote", &url]);
+    assert!(info_refs.contains("refs/tags/v1^{}"));
+    assert!(!info_refs.contains(&"0".repeat(40)));
+    assert!(common::refs(&clone).contains("v1"));
+    fs::remove_dir_all(&scratch).unwrap_or_default();
+}
+
+#[test]
+fn clones_through_alternates() {
+    let scratch = scratch("alternates");
+    let git_root = scratch.join("root");
+    let work = scratch.join("work");
+    fs::create_dir_all(&work).unwrap();
+    fixture(&git_root, &work);
+    let user_root = git_root.join("snoot");
+    git(
+        &user_root,
+        &[
+            "clone",
+            "--quiet",
+            "--bare",
+            "--shared",
+            "fixture.git",
+            "borrower.git",
+        ],
+    );
+    let server = serve(&git_root);
+
+    // git won't follow alternates to other urls unless it's told it can
+    let url = format!("http://127.0.0.1:{}/snoot/borrower", server.port);
+    git(
+        &scratch,
+        &[
+            "-c",
+            "http.followRedirects=true",
+            "clone",
+            "--quiet",
+            &url,
+            "clone",
+        ],
+    );
+    let clone = scratch.join("clone");
+    git(&clone, &["fsck", "--strict"]);
+    assert_eq!(
+        git(&clone, &["rev-parse", "origin/main"]),
+        git(&user_root.join("fixture.git"), &["rev-parse", "main"])
+    );
+    fs::remove_dir_all(&scratch).unwrap_or_default();
+}
@@ -0,0 +1,72 @@
+// mirrors a file:// upstream and checks it keeps up, including deleted
+// branches
+mod common;
+
+use common::{git, refs, scratch, serve};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::{fs, thread, time};
+
+fn get(port: u16, path: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(stream, "GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+// the first sync happens when the server starts, and the rest every second
+fn wait_for_sync(upstream: &Path, mirror: &Path) {
+    for _ in 0..100 {
+        if refs(mirror) == refs(upstream) {
+            return;
+        }
+        thread::sleep(time::Duration::from_millis(100));
+    }
+    assert_eq!(refs(mirror), refs(upstream));
+}
+
+fn commit(work: &Path, message: &str) {
+    fs::write(work.join("file"), message).unwrap();
+    git(work, &["add", "file"]);
+    git(work, &["commit", "--quiet", "--message", message]);
+}
+
+#[test]
+fn mirrors_file_upstreams() {
+    let scratch = scratch("mirror");
+    let upstream = scratch.join("upstream");
+    fs::create_dir_all(&upstream).unwrap();
+    git(&upstream, &["init", "--quiet", "--initial-branch=trunk"]);
+    commit(&upstream, "one");
+    git(&upstream, &["tag", "--annotate", "v1", "--message", "one"]);
+    git(&upstream, &["branch", "doomed"]);
+    commit(&upstream, "two");
+
+    let git_root = scratch.join("root");
+    let mirror = git_root.join("snoot").join("mirror.git");
+    fs::create_dir_all(&mirror).unwrap();
+    git(&mirror, &["init", "--quiet", "--bare"]);
+    let url = format!("file://{}", upstream.display());
+    git(&mirror, &["config", "snootforge.mirror", &url]);
+    git(&mirror, &["config", "snootforge.mirrorinterval", "1"]);
+
+    let server = serve(&git_root);
+    wait_for_sync(&upstream, &mirror);
+    assert_eq!(
+        git(&mirror, &["symbolic-ref", "HEAD"]),
+        "refs/heads/trunk\n"
+    );
+
+    git(&upstream, &["branch", "--delete", "--force", "doomed"]);
+    commit(&upstream, "three");
+    wait_for_sync(&upstream, &mirror);
+    assert!(!refs(&mirror).contains("doomed"));
+
+    let page = get(server.port, "/snoot/mirror");
+    assert!(page.contains("mirrored from"));
+    assert!(page.contains(&url));
+    assert!(page.contains("last synced"));
+    fs::remove_dir_all(&scratch).unwrap_or_default();
+}
@@ -0,0 +1,139 @@
+// pushes over smart http with a real git, with and without the right
+// credentials, and with history rewritten under it
+mod common;
+
+use common::{bare_repo, git, scratch, serve};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::Command;
+
+// hunter2 for snoot and swordfish for other, at 1000 iterations so the tests
+// don't sit there hashing
+const SNOOT_PASSWD: &str =
+    "pepper:1000:97d7b1cabe059e02230ec294c7e29f03c25daccbb566a63dcaaced9e89aafac0\n";
+const OTHER_PASSWD: &str =
+    "salt:1000:2fc5582ffd5b245667e2c53eeb6ccc601c94e36daeb3195be7ff8648c6d9f245\n";
+
+// git that's allowed to fail, with what it said on the way
+fn try_git(directory: &Path, args: &[&str]) -> (bool, String) {
+    let output = Command::new("git")
+        .args(["-c", "credential.helper="])
+        .args(args)
+        .current_dir(directory)
+        .env("GIT_AUTHOR_NAME", "snoot")
+        .env("GIT_AUTHOR_EMAIL", "snoot@example.com")
+        .env("GIT_COMMITTER_NAME", "snoot")
+        .env("GIT_COMMITTER_EMAIL", "snoot@example.com")
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .expect("couldn't run git");
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+// a bare repo for snoot, a clone of it to push from, and passwd files for
+// snoot and other
+fn setup(name: &str) -> (std::path::PathBuf, common::Server, std::path::PathBuf) {
+    let scratch = scratch(name);
+    let git_root = scratch.join("root");
+    bare_repo(&git_root, &scratch.join("work"), "repo");
+    fs::write(git_root.join("snoot").join("passwd"), SNOOT_PASSWD).unwrap();
+    fs::create_dir_all(git_root.join("other")).unwrap();
+    fs::write(git_root.join("other").join("passwd"), OTHER_PASSWD).unwrap();
+    let server = serve(&git_root);
+    let url = format!("http://127.0.0.1:{}/snoot/repo.git", server.port);
+    git(&scratch, &["clone", "--quiet", &url, "clone"]);
+    (scratch, server, git_root.join("snoot").join("repo.git"))
+}
+
+fn commit(work: &Path, message: &str) {
+    fs::write(work.join("file"), message).unwrap();
+    git(work, &["commit", "--quiet", "--all", "--message", message]);
+}
+
+fn url(server: &common::Server, credentials: &str) -> String {
+    format!(
+        "http://{}@127.0.0.1:{}/snoot/repo.git",
+        credentials, server.port
+    )
+}
+
+#[test]
+fn asks_for_credentials() {
+    let (scratch, server, bare) = setup("push-anonymous");
+    let clone = scratch.join("clone");
+    commit(&clone, "anonymous");
+    let before = git(&bare, &["rev-parse", "main"]);
+
+    let mut stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    write!(
+        stream,
+        "GET /snoot/repo.git/info/refs?service=git-receive-pack HTTP/1.0\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.0 401"));
+    assert!(response.to_lowercase().contains("www-authenticate: basic"));
+
+    let (pushed, _) = try_git(&clone, &["push", "--quiet", "origin", "main"]);
+    assert!(!pushed);
+    assert_eq!(git(&bare, &["rev-parse", "main"]), before);
+    fs::remove_dir_all(&scratch).unwrap_or_default();
+}
+
+#[test]
+fn only_lets_the_owner_push() {
+    let (scratch, server, bare) = setup("push-owner");
+    let clone = scratch.join("clone");
+    commit(&clone, "owner");
+    let before = git(&bare, &["rev-parse", "main"]);
+
+    for credentials in &["other:swordfish", "snoot:swordfish", "snoot:hunter3"] {
+        let (pushed, _) = try_git(
+            &clone,
+            &["push", "--quiet", &url(&server, credentials), "main"],
+        );
+        assert!(!pushed, "{} could push", credentials);
+        assert_eq!(git(&bare, &["rev-parse", "main"]), before);
+    }
+
+    let (pushed, stderr) = try_git(
+        &clone,
+        &["push", "--quiet", &url(&server, "snoot:hunter2"), "main"],
+    );
+    assert!(pushed, "{}", stderr);
+    assert_eq!(
+        git(&bare, &["rev-parse", "main"]),
+        git(&clone, &["rev-parse", "main"])
+    );
+    fs::remove_dir_all(&scratch).unwrap_or_default();
+}
+
+#[test]
+fn rejects_non_fast_forwards_unless_allowed() {
+    let (scratch, server, bare) = setup("push-force");
+    let clone = scratch.join("clone");
+    let owner = url(&server, "snoot:hunter2");
+    git(&clone, &["reset", "--quiet", "--hard", "HEAD~1"]);
+    commit(&clone, "rewritten");
+    let before = git(&bare, &["rev-parse", "main"]);
+
+    let (pushed, stderr) = try_git(&clone, &["push", "--force", &owner, "main"]);
+    assert!(!pushed);
+    assert!(stderr.contains("non-fast-forward"), "{}", stderr);
+    assert_eq!(git(&bare, &["rev-parse", "main"]), before);
+
+    git(&bare, &["config", "snootforge.allownonfastforward", "true"]);
+    let (pushed, stderr) = try_git(&clone, &["push", "--quiet", "--force", &owner, "main"]);
+    assert!(pushed, "{}", stderr);
+    assert_eq!(
+        git(&bare, &["rev-parse", "main"]),
+        git(&clone, &["rev-parse", "main"])
+    );
+    fs::remove_dir_all(&scratch).unwrap_or_default();
+}
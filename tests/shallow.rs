@@ -0,0 +1,152 @@
+// shallow and partial clones over smart http with a real git, in protocol v0
+// and v2
+mod common;
+
+use common::{git, git_with_env, scratch, serve};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const VERSIONS: [&str; 2] = ["protocol.version=0", "protocol.version=2"];
+
+// five commits a day apart, with a tag on the second, a small file that
+// changes every time and a big one that changes twice
+fn fixture(git_root: &Path, work: &Path) -> PathBuf {
+    fs::create_dir_all(work).unwrap();
+    git(work, &["init", "--quiet", "--initial-branch=main"]);
+    for number in 1..6 {
+        fs::write(work.join("file"), number.to_string()).unwrap();
+        if number == 1 || number == 4 {
+            fs::write(work.join("big"), number.to_string().repeat(2048)).unwrap();
+        }
+        git(work, &["add", "file", "big"]);
+        let date = format!("2020-01-0{}T12:00:00+0000", number);
+        git_with_env(
+            work,
+            &["commit", "--quiet", "--message", &number.to_string()],
+            &[("GIT_AUTHOR_DATE", &date), ("GIT_COMMITTER_DATE", &date)],
+        );
+        if number == 2 {
+            git(work, &["tag", "two"]);
+        }
+    }
+    let bare = git_root.join("snoot").join("repo.git");
+    fs::create_dir_all(&bare).unwrap();
+    git(
+        git_root,
+        &[
+            "clone",
+            "--quiet",
+            "--bare",
+            work.to_str().unwrap(),
+            bare.to_str().unwrap(),
+        ],
+    );
+    bare
+}
+
+// clones with the extra args in every protocol version, and checks each
+// clone with check
+fn clone_each_way(name: &str, args: &[&str], check: impl Fn(&str, &Path, &Path)) {
+    let scratch = scratch(name);
+    let git_root = scratch.join("root");
+    let bare = fixture(&git_root, &scratch.join("work"));
+    let server = serve(&git_root);
+    let url = format!("http://127.0.0.1:{}/snoot/repo.git", server.port);
+    for (index, version) in VERSIONS.iter().enumerate() {
+        let clone = format!("clone-{}", index);
+        let mut clone_args = vec!["-c", version, "clone", "--quiet"];
+        clone_args.extend_from_slice(args);
+        clone_args.extend_from_slice(&[&url, &clone]);
+        git(&scratch, &clone_args);
+        check(version, &scratch.join(clone), &bare);
+    }
+    fs::remove_dir_all(&scratch).unwrap_or_default();
+}
+
+fn log(clone: &Path) -> String {
+    git(clone, &["log", "--format=%s", "HEAD"])
+}
+
+// the objects the clone hasn't got, without fetching them to find out
+fn missing(clone: &Path) -> Vec<String> {
+    git(
+        clone,
+        &["rev-list", "--objects", "--all", "--missing=print"],
+    )
+    .lines()
+    .filter(|line| line.starts_with('?'))
+    .map(|line| line[1..].to_owned())
+    .collect()
+}
+
+#[test]
+fn clones_to_a_depth_and_deepens() {
+    clone_each_way("shallow-depth", &["--depth", "1"], |version, clone, _| {
+        assert_eq!(log(clone), "5\n");
+        git(clone, &["-c", version, "fetch", "--quiet", "--deepen", "2"]);
+        assert_eq!(log(clone), "5\n4\n3\n");
+        git(clone, &["fsck", "--strict"]);
+    });
+}
+
+#[test]
+fn clones_since_a_date() {
+    clone_each_way(
+        "shallow-since",
+        &["--shallow-since", "2020-01-03T00:00:00+0000"],
+        |_, clone, _| {
+            assert_eq!(log(clone), "5\n4\n3\n");
+            git(clone, &["fsck", "--strict"]);
+        },
+    );
+}
+
+#[test]
+fn clones_excluding_a_ref() {
+    clone_each_way(
+        "shallow-exclude",
+        &["--shallow-exclude", "two"],
+        |_, clone, _| {
+            assert_eq!(log(clone), "5\n4\n3\n");
+            git(clone, &["fsck", "--strict"]);
+        },
+    );
+}
+
+// the checkout fetches the blobs it needs as it goes
+#[test]
+fn clones_without_blobs() {
+    clone_each_way(
+        "partial-none",
+        &["--filter", "blob:none"],
+        |version, clone, bare| {
+            assert_eq!(fs::read_to_string(clone.join("file")).unwrap(), "5");
+            assert!(missing(clone)
+                .contains(&git(bare, &["rev-parse", "main~1:file"]).trim().to_owned()));
+            git(clone, &["-c", version, "checkout", "--quiet", "main~3"]);
+            assert_eq!(fs::read_to_string(clone.join("file")).unwrap(), "2");
+            assert_eq!(log(clone), "2\n1\n");
+        },
+    );
+}
+
+#[test]
+fn clones_without_big_blobs() {
+    clone_each_way(
+        "partial-limit",
+        &["--filter", "blob:limit=1k", "--no-checkout"],
+        |version, clone, bare| {
+            let blob = |revision: &str| git(bare, &["rev-parse", revision]).trim().to_owned();
+            let mut missing = missing(clone);
+            missing.sort();
+            let mut big = vec![blob("main:big"), blob("main~4:big")];
+            big.sort();
+            assert_eq!(missing, big);
+            git(clone, &["-c", version, "checkout", "--quiet", "main"]);
+            assert_eq!(
+                fs::read_to_string(clone.join("big")).unwrap(),
+                "4".repeat(2048)
+            );
+        },
+    );
+}
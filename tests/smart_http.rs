@@ -0,0 +1,196 @@
+// clones and fetches over smart http with a real git. the fetches after
+// something new lands upstream are the ones that have to negotiate, which a
+// clone never does
+mod common;
+
+use common::{bare_repo, git, scratch, serve};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+
+// a new commit in work, pushed to the bare repo behind the server's back
+fn land(work: &Path, bare: &Path, message: &str) {
+    fs::write(work.join("file"), message).unwrap();
+    git(work, &["commit", "--quiet", "--all", "--message", message]);
+    git(work, &["push", "--quiet", bare.to_str().unwrap(), "main"]);
+}
+
+// more local commits than git sends haves for in its first request, so the
+// fetch goes more than one round before it finds what's common
+fn diverge(clone: &Path) {
+    git(clone, &["checkout", "--quiet", "-b", "local"]);
+    for number in 0..40 {
+        fs::write(clone.join("local"), number.to_string()).unwrap();
+        git(clone, &["add", "local"]);
+        git(
+            clone,
+            &[
+                "commit",
+                "--quiet",
+                "--message",
+                &format!("local {}", number),
+            ],
+        );
+    }
+}
+
+fn pkt(line: &str) -> String {
+    format!("{:04x}{}", line.len() + 4, line)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+// http/1.0, so the response ends when the connection does instead of coming
+// in chunks
+fn request(port: u16, request_line: &str, headers: &[&str], body: &[u8]) -> Vec<u8> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(stream, "{} HTTP/1.0\r\nHost: localhost\r\n", request_line).unwrap();
+    for header in headers {
+        write!(stream, "{}\r\n", header).unwrap();
+    }
+    write!(stream, "Content-Length: {}\r\n\r\n", body.len()).unwrap();
+    stream.write_all(body).unwrap();
+    let mut response = vec![];
+    stream.read_to_end(&mut response).unwrap();
+    response
+}
+
+fn with_version<'a>(version: &'a str, args: &[&'a str]) -> Vec<&'a str> {
+    let mut all = vec!["-c", version];
+    all.extend_from_slice(args);
+    all
+}
+
+// clones, then fetches after each of a few commits land upstream
+fn clone_and_fetch(name: &str, version: &str) {
+    let scratch = scratch(name);
+    let git_root = scratch.join("root");
+    let work = scratch.join("work");
+    let bare = bare_repo(&git_root, &work, "repo");
+    let server = serve(&git_root);
+    let url = format!("http://127.0.0.1:{}/snoot/repo.git", server.port);
+
+    git(
+        &scratch,
+        &with_version(version, &["clone", "--quiet", &url, "clone"]),
+    );
+    let clone = scratch.join("clone");
+    diverge(&clone);
+    for number in 2..5 {
+        land(&work, &bare, &number.to_string());
+        git(&clone, &with_version(version, &["fetch", "--quiet"]));
+        assert_eq!(
+            git(&clone, &["rev-parse", "origin/main"]),
+            git(&bare, &["rev-parse", "main"])
+        );
+    }
+    git(&clone, &["fsck", "--strict"]);
+    assert_eq!(
+        git(&clone, &["log", "--format=%s", "origin/main"]),
+        "4\n3\n2\n1\n0\n"
+    );
+    fs::remove_dir_all(&scratch).unwrap_or_default();
+}
+
+#[test]
+fn fetches_over_v0() {
+    clone_and_fetch("smart-v0", "protocol.version=0");
+}
+
+#[test]
+fn fetches_over_v1() {
+    clone_and_fetch("smart-v1", "protocol.version=1");
+}
+
+#[test]
+fn fetches_over_v2() {
+    clone_and_fetch("smart-v2", "protocol.version=2");
+}
+
+// the refs are filtered on our end, not by the client after it gets them
+#[test]
+fn lists_refs_by_prefix() {
+    let scratch = scratch("smart-ls-refs");
+    let git_root = scratch.join("root");
+    let bare = bare_repo(&git_root, &scratch.join("work"), "repo");
+    git(&bare, &["branch", "other", "main"]);
+    git(
+        &bare,
+        &["tag", "--annotate", "v1", "--message", "one", "main"],
+    );
+    let server = serve(&git_root);
+    let url = format!("http://127.0.0.1:{}/snoot/repo.git", server.port);
+
+    let tags = git(
+        &scratch,
+        &["-c", "protocol.version=2", "ls-remote", "--tags", &url],
+    );
+    let names: Vec<&str> = tags
+        .lines()
+        .map(|line| line.split('\t').nth(1).unwrap())
+        .collect();
+    assert_eq!(names, ["refs/tags/v1", "refs/tags/v1^{}"]);
+
+    let mut body = pkt("command=ls-refs\n");
+    body += "0001";
+    body += &pkt("peel\n");
+    body += &pkt("ref-prefix refs/heads/\n");
+    body += "0000";
+    let response = request(
+        server.port,
+        "POST /snoot/repo.git/git-upload-pack",
+        &["Git-Protocol: version=2"],
+        body.as_bytes(),
+    );
+    assert!(response.starts_with(b"HTTP/1.0 200"));
+    assert!(contains(&response, b" refs/heads/main\n"));
+    assert!(contains(&response, b" refs/heads/other\n"));
+    assert!(!contains(&response, b"refs/tags/"));
+    assert!(!contains(&response, b" HEAD"));
+    fs::remove_dir_all(&scratch).unwrap_or_default();
+}
+
+// what git would send for a clone of main, straight to the endpoint, so
+// there's no falling back to the dumb protocol without anyone noticing
+#[test]
+fn answers_upload_pack_posts() {
+    let scratch = scratch("smart-post");
+    let git_root = scratch.join("root");
+    let bare = bare_repo(&git_root, &scratch.join("work"), "repo");
+    let server = serve(&git_root);
+    let main = git(&bare, &["rev-parse", "main"]);
+
+    let advertisement = request(
+        server.port,
+        "GET /snoot/repo.git/info/refs?service=git-upload-pack",
+        &[],
+        &[],
+    );
+    assert!(advertisement.starts_with(b"HTTP/1.0 200"));
+    assert!(contains(
+        &advertisement,
+        b"application/x-git-upload-pack-advertisement"
+    ));
+    assert!(contains(&advertisement, b"multi_ack_detailed"));
+
+    let mut body = String::new();
+    body += &pkt(&format!("want {} side-band-64k ofs-delta\n", main.trim()));
+    body += "0000";
+    body += &pkt("done\n");
+    let response = request(
+        server.port,
+        "POST /snoot/repo.git/git-upload-pack",
+        &[],
+        body.as_bytes(),
+    );
+    assert!(response.starts_with(b"HTTP/1.0 200"));
+    assert!(contains(&response, b"application/x-git-upload-pack-result"));
+    assert!(contains(&response, b"\r\n\r\n0008NAK\n"));
+    assert!(contains(&response, b"PACK"));
+    fs::remove_dir_all(&scratch).unwrap_or_default();
+}
@@ -0,0 +1,99 @@
+// clones and pushes over ssh with a real git and openssh, and checks nobody
+// can push to somebody else's repos
+mod common;
+
+use common::{bare_repo, free_port, git, git_with_env, scratch, serve_with};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn keygen(path: &Path) {
+    let status = Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+        .arg(path)
+        .status()
+        .expect("couldn't run ssh-keygen");
+    assert!(status.success());
+}
+
+#[test]
+fn clones_and_pushes() {
+    let scratch = scratch("ssh");
+    let git_root = scratch.join("root");
+    let bare = bare_repo(&git_root, &scratch.join("work"), "pushed");
+    let theirs = git_root.join("other").join("theirs.git");
+    fs::create_dir_all(&theirs).unwrap();
+    git(&theirs, &["init", "--quiet", "--bare"]);
+
+    // the server looks for its host key in its working directory
+    keygen(&scratch.join("ssh_host_ed25519_key"));
+    let key = scratch.join("id_ed25519");
+    keygen(&key);
+    fs::copy(
+        scratch.join("id_ed25519.pub"),
+        git_root.join("snoot").join("authorized_keys"),
+    )
+    .unwrap();
+    let ssh_port = free_port();
+    let _server = serve_with(
+        &git_root,
+        &["--ssh-port", &ssh_port.to_string()],
+        &[ssh_port],
+    );
+
+    let ssh = format!(
+        "ssh -i {} -o IdentitiesOnly=yes -o StrictHostKeyChecking=no \
+         -o UserKnownHostsFile=/dev/null -o LogLevel=ERROR",
+        key.display()
+    );
+    let ssh_git = |directory: &Path, args: &[&str]| {
+        git_with_env(directory, args, &[("GIT_SSH_COMMAND", &ssh)])
+    };
+    let url = format!("ssh://snoot@127.0.0.1:{}/snoot/pushed.git", ssh_port);
+    ssh_git(&scratch, &["clone", "--quiet", &url, "clone"]);
+    let clone = scratch.join("clone");
+    git(&clone, &["fsck", "--strict"]);
+    assert_eq!(git(&clone, &["log", "--format=%s"]), "1\n0\n");
+
+    // v2 is asked for with GIT_PROTOCOL, which ssh sends before the command
+    let trace = scratch.join("trace");
+    git_with_env(
+        &scratch,
+        &[
+            "-c",
+            "protocol.version=2",
+            "clone",
+            "--quiet",
+            &url,
+            "clone-v2",
+        ],
+        &[
+            ("GIT_SSH_COMMAND", &ssh),
+            ("GIT_TRACE_PACKET", trace.to_str().unwrap()),
+        ],
+    );
+    assert!(fs::read_to_string(&trace).unwrap().contains("< version 2"));
+    assert_eq!(
+        git(&scratch.join("clone-v2"), &["log", "--format=%s"]),
+        "1\n0\n"
+    );
+
+    fs::write(clone.join("file"), "pushed").unwrap();
+    git(&clone, &["commit", "--quiet", "--all", "--message", "2"]);
+    ssh_git(&clone, &["push", "--quiet", "origin", "main"]);
+    assert_eq!(
+        git(&bare, &["rev-parse", "main"]),
+        git(&clone, &["rev-parse", "main"])
+    );
+
+    let url = format!("ssh://snoot@127.0.0.1:{}/other/theirs.git", ssh_port);
+    let status = Command::new("git")
+        .args(["push", "--quiet", &url, "main"])
+        .env("GIT_SSH_COMMAND", &ssh)
+        .current_dir(&clone)
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    assert_eq!(common::refs(&theirs), "");
+    fs::remove_dir_all(&scratch).unwrap_or_default();
+}